@@ -0,0 +1,86 @@
+//! 统计重传一个满窗口时的内存分配次数。
+//!
+//! `Arc<[u8]>`一栏剩下的分配来自`mpsc`信道内部按块增长的链表,与包本身无关。
+//!
+//! `cargo bench --bench retransmit`
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::time::Instant;
+
+use go_back_n::connection::{Connection, PacketWrapper, TIMEOUT_DURATION};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ROUNDS: usize = 1000;
+
+fn count_allocations<F: FnMut()>(mut f: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+/// 旧的做法: 未确认队列里保存`Box<[u8]>`,每次重传都复制整个包
+fn boxed_retransmit(window: usize, packet_size: usize) -> (usize, u128) {
+    let (tx, rx) = channel();
+    let unacked = (0..window).map(|_| vec![0u8; packet_size].into_boxed_slice()).collect::<VecDeque<_>>();
+    let start = Instant::now();
+    let allocations = count_allocations(|| {
+        for _ in 0..ROUNDS {
+            for packet in unacked.iter() {
+                tx.send(packet.clone()).unwrap();
+            }
+            rx.try_iter().for_each(drop);
+        }
+    });
+    (allocations, start.elapsed().as_nanos())
+}
+
+/// 现在的做法: 通过`Connection::on_tick_at`触发超时重传
+fn connection_retransmit() -> (usize, usize, u128) {
+    let (tx, rx) = channel::<PacketWrapper>();
+    let mut c = Connection::new(0, tx);
+    c.enqueue(&vec![0u8; 64 * Connection::MAX_BODY_SIZE as usize]);
+    for _ in 0..64 {
+        c.send_if_could().unwrap();
+    }
+    rx.try_iter().for_each(drop);
+    let window = c.unacked_len();
+    let mut now = Instant::now();
+    let start = Instant::now();
+    let allocations = count_allocations(|| {
+        for _ in 0..ROUNDS {
+            now += TIMEOUT_DURATION;
+            c.on_tick_at(now).unwrap();
+            rx.try_iter().for_each(drop);
+        }
+    });
+    (window, allocations, start.elapsed().as_nanos())
+}
+
+fn main() {
+    let (window, arc_allocations, arc_nanos) = connection_retransmit();
+    let packet_size = go_back_n::packet::Header::SIZE + Connection::MAX_BODY_SIZE as usize;
+    let (box_allocations, box_nanos) = boxed_retransmit(window, packet_size);
+    println!("retransmitting a window of {} packets ({} bytes each), {} rounds", window, packet_size, ROUNDS);
+    println!("{:<12} {:>16} {:>16}", "buffer", "allocs/window", "ns/window");
+    println!("{:<12} {:>16.2} {:>16}", "Box<[u8]>", box_allocations as f64 / ROUNDS as f64, box_nanos / ROUNDS as u128);
+    println!("{:<12} {:>16.2} {:>16}", "Arc<[u8]>", arc_allocations as f64 / ROUNDS as f64, arc_nanos / ROUNDS as u128);
+}
@@ -0,0 +1,179 @@
+//! 两个`Connection`互相传输数据,中间的信道完全由输入控制: 丢包、重复、乱序、截断、
+//! 追加垃圾字节、伪造只有首部的控制包以及注入任意字节。
+//!
+//! 断言:
+//! - 不恐慌
+//! - 内存有界: 未确认的包不超过窗口,发送缓冲区不超过容量,每次处理一个事件产生的包有上限
+//! - 不会交付从未发送过的数据: 收到的字节流总是对端真实数据的前缀
+//!
+//! GBN首部没有校验和,翻转真实数据包里的比特必然会被交付出去,所以信道不会改动真实包的内容,
+//! 注入的字节若能解析成带数据的包也会被丢掉——它们和对端真正发出的包无法区分。
+
+#![no_main]
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use zerocopy::AsBytes;
+
+use go_back_n::connection::{Connection, PacketWrapper, SendSequenceSpace};
+use go_back_n::packet::{Header, Packet, PacketBuf};
+
+/// 处理一个事件最多产生的包数: 一整个窗口的重传,外加一个ACK
+const MAX_BURST: usize = SendSequenceSpace::N as usize + 1;
+
+#[derive(Arbitrary, Debug)]
+enum Action {
+    /// 送达信道中第`index`个包(取模),其余的包保持顺序
+    Deliver(u8),
+    Drop(u8),
+    Duplicate(u8),
+    Truncate(u8, u8),
+    Extend(u8, Vec<u8>),
+    Forge { to_right: bool, seq_num: u32, ack_num: u32, flags: u16 },
+    Inject { to_right: bool, bytes: Vec<u8> },
+    Write { right: bool, len: u16 },
+    Advance(u16),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    isn: [u32; 2],
+    actions: Vec<Action>,
+}
+
+struct Pair {
+    now: Instant,
+    conns: [Connection; 2],
+    rx: Receiver<PacketWrapper>,
+    in_flight: VecDeque<(usize, PacketBuf)>,
+    written: [Vec<u8>; 2],
+    received: [Vec<u8>; 2],
+}
+
+impl Pair {
+    fn collect(&mut self) {
+        let mut burst = 0;
+        for packet in self.rx.try_iter() {
+            burst += 1;
+            self.in_flight.push_back((packet.id ^ 1, packet.data));
+        }
+        assert!(burst <= MAX_BURST, "one event produced {} packets", burst);
+        // 信道本身也要有界,否则输入可以让它无限增长
+        while self.in_flight.len() > 4 * MAX_BURST {
+            self.in_flight.pop_front();
+        }
+    }
+
+    fn deliver(&mut self, to: usize, packet: &[u8]) {
+        let _ = self.conns[to].on_packet_at(packet, self.now);
+        self.check(to);
+    }
+
+    fn check(&mut self, i: usize) {
+        let c = &mut self.conns[i];
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = c.read_incoming(&mut buf);
+            if n == 0 {
+                break;
+            }
+            self.received[i].extend_from_slice(&buf[..n]);
+        }
+        assert!(self.written[1 - i].starts_with(&self.received[i]), "delivered data that was never sent");
+        assert!(c.unacked_len() <= SendSequenceSpace::N as usize);
+        assert!(c.unsent_len() <= Connection::SEND_BUFFER_SIZE);
+        self.collect();
+    }
+
+    fn take(&mut self, index: u8) -> Option<(usize, PacketBuf)> {
+        if self.in_flight.is_empty() {
+            return None;
+        }
+        let index = index as usize % self.in_flight.len();
+        self.in_flight.remove(index)
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let (tx, rx) = channel();
+    let now = Instant::now();
+    let mut conns = [
+        Connection::with_initial_seq_num(0, tx.clone(), input.isn[0]),
+        Connection::with_initial_seq_num(1, tx, input.isn[1]),
+    ];
+    for c in conns.iter_mut() {
+        c.connect_at(now).unwrap();
+    }
+    let mut pair = Pair {
+        now,
+        conns,
+        rx,
+        in_flight: VecDeque::new(),
+        written: [Vec::new(), Vec::new()],
+        received: [Vec::new(), Vec::new()],
+    };
+    pair.collect();
+
+    for action in input.actions {
+        match action {
+            Action::Deliver(index) => {
+                if let Some((to, packet)) = pair.take(index) {
+                    pair.deliver(to, &packet);
+                }
+            }
+            Action::Drop(index) => {
+                pair.take(index);
+            }
+            Action::Duplicate(index) => {
+                if let Some((to, packet)) = pair.take(index) {
+                    pair.deliver(to, &packet);
+                    pair.deliver(to, &packet);
+                }
+            }
+            Action::Truncate(index, len) => {
+                if let Some((to, packet)) = pair.take(index) {
+                    let len = len as usize % packet.len();
+                    pair.deliver(to, &packet[..len]);
+                }
+            }
+            Action::Extend(index, junk) => {
+                if let Some((to, packet)) = pair.take(index) {
+                    if !junk.is_empty() {
+                        let extended = packet.iter().chain(junk.iter()).copied().collect::<Vec<_>>();
+                        pair.deliver(to, &extended);
+                    }
+                }
+            }
+            Action::Forge { to_right, seq_num, ack_num, flags } => {
+                let mut header = Header::new(seq_num, ack_num, 0, false);
+                header.flags.set(flags);
+                pair.deliver(to_right as usize, header.as_bytes());
+            }
+            Action::Inject { to_right, bytes } => {
+                if Packet::parse(bytes.as_slice()).map_or(true, |packet| !packet.is_sequenced()) {
+                    pair.deliver(to_right as usize, &bytes);
+                }
+            }
+            Action::Write { right, len } => {
+                let i = right as usize;
+                let start = pair.written[i].len();
+                let data = (start..start + len as usize).map(|n| (n as u32).wrapping_mul(2_654_435_761) as u8).collect::<Vec<_>>();
+                let accepted = pair.conns[i].enqueue(&data);
+                pair.written[i].extend_from_slice(&data[..accepted]);
+                let _ = pair.conns[i].send_if_could_at(pair.now);
+                pair.check(i);
+            }
+            Action::Advance(ms) => {
+                pair.now += Duration::from_millis(ms as u64);
+                for i in 0..2 {
+                    let _ = pair.conns[i].on_tick_at(pair.now);
+                    pair.check(i);
+                }
+            }
+        }
+    }
+});
@@ -0,0 +1,28 @@
+//! 任意字节交给`Packet::parse`: 不能恐慌,解析成功时首部和数据必须与原始字节一致。
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use go_back_n::packet::{FrameType, Header, Packet, ParseError, Summary};
+
+fuzz_target!(|data: &[u8]| {
+    match Packet::parse(data) {
+        Ok(packet) => {
+            assert_eq!(packet.header.bytes(), &data[..Header::SIZE]);
+            assert_eq!(packet.body, &data[Header::SIZE..]);
+            assert_eq!(packet.get_body_len() as usize, packet.body.len());
+            assert_eq!(packet.header.flags.get() & !Header::KNOWN_FLAGS, 0);
+        }
+        Err(ParseError::TooShort(len)) => assert!(len < Header::SIZE && len == data.len()),
+        Err(ParseError::BodyLengthMismatch { declared, actual }) => {
+            assert_eq!(actual, data.len() - Header::SIZE);
+            assert_ne!(declared as usize, actual);
+        }
+        Err(ParseError::UnknownFlags(flags)) => assert_ne!(flags & !Header::KNOWN_FLAGS, 0),
+        Err(ParseError::UnknownFrameType(frame_type)) => assert!(FrameType::from_u8(frame_type).is_none()),
+        // 只有预共享密钥模式的连接会报告
+        Err(ParseError::Forged) | Err(ParseError::Replayed) => unreachable!(),
+    }
+    let _ = Summary(data).to_string();
+});
@@ -0,0 +1,63 @@
+//! 在异步运行时中使用`GbnStream`,读写不会阻塞执行器的线程
+use std::io::Result;
+use std::net::Shutdown;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::io::{AsyncRead, AsyncWrite};
+
+use super::{Endpoint, GbnStream};
+use super::connection::{ConnectionSnapshot, ConnectionStats};
+
+/// 实现了`futures`的`AsyncRead`和`AsyncWrite`的`GbnStream`。
+/// 数据到达或者发送缓冲区有了空间时由`packet_loop`唤醒等待的任务;`close`关闭写方向
+pub struct AsyncGbnStream {
+    endpoint: Arc<Endpoint>,
+}
+
+impl GbnStream {
+    pub fn into_async(self) -> AsyncGbnStream {
+        AsyncGbnStream { endpoint: self.endpoint }
+    }
+}
+
+impl From<GbnStream> for AsyncGbnStream {
+    fn from(stream: GbnStream) -> Self {
+        stream.into_async()
+    }
+}
+
+impl AsyncGbnStream {
+    pub fn into_inner(self) -> GbnStream {
+        GbnStream { endpoint: self.endpoint }
+    }
+    pub fn stats(&self) -> ConnectionStats {
+        self.endpoint.lock().stats().clone()
+    }
+    pub fn snapshot(&self) -> ConnectionSnapshot {
+        self.endpoint.lock().snapshot()
+    }
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        self.endpoint.shutdown(how)
+    }
+}
+
+impl AsyncRead for AsyncGbnStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        self.endpoint.poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for AsyncGbnStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.endpoint.poll_write(cx, buf)
+    }
+    /// 发送不需要等待,数据交给连接后由重传保证送达
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(self.endpoint.flush())
+    }
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(self.endpoint.shutdown(Shutdown::Write))
+    }
+}
@@ -0,0 +1,64 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::process;
+
+use clap::{Parser, Subcommand};
+use log::error;
+
+use go_back_n::cli::ConnectionOpt;
+use go_back_n::tunnel;
+use go_back_n::udp::UdpInterface;
+
+/// 通过UDP上的go_back_n连接转发TCP连接
+#[derive(Parser)]
+#[command(name = "gbn_tunnel")]
+struct Opt {
+    /// 本地UDP地址
+    #[arg(long, default_value = "0.0.0.0:0")]
+    udp: SocketAddr,
+    #[command(flatten)]
+    connection: ConnectionOpt,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 在本地TCP端口上接受连接,转发给对端的`forward`进程
+    Listen {
+        /// 本地TCP地址
+        #[arg(long)]
+        tcp: SocketAddr,
+        /// 对端的UDP地址
+        #[arg(long)]
+        peer: SocketAddr,
+    },
+    /// 接受对端`listen`进程的连接,转发给目标TCP地址
+    Forward {
+        /// 目标TCP地址
+        #[arg(long)]
+        target: SocketAddr,
+    },
+}
+
+fn run(opt: Opt) -> io::Result<()> {
+    let udp = UdpInterface::bind_with_config(opt.udp, opt.connection.into_config()?)?;
+    eprintln!("udp on {}", udp.local_addr()?);
+    match opt.command {
+        Command::Listen { tcp, peer } => tunnel::listen(&udp, TcpListener::bind(tcp)?, peer),
+        Command::Forward { target } => tunnel::forward(&udp, target),
+    }
+}
+
+fn main() {
+    pretty_env_logger::init();
+    // 传输停滞时用`kill -USR1`查看所有连接的状态
+    #[cfg(unix)]
+    if let Err(e) = go_back_n::dump_on_signal() {
+        error!("failed to install the SIGUSR1 handler: {}", e);
+    }
+    if let Err(e) = run(Opt::parse()) {
+        error!("{}", e);
+        process::exit(1);
+    }
+}
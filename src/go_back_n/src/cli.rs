@@ -0,0 +1,62 @@
+//! 命令行程序共用的参数
+use std::io;
+use std::time::Duration;
+
+use clap::Args;
+
+use super::connection::Config;
+use super::crypto::Psk;
+
+/// 连接参数,不指定的沿用库的默认值
+#[derive(Args, Clone)]
+pub struct ConnectionOpt {
+    /// 发送窗口大小N
+    #[arg(long)]
+    pub window: Option<u32>,
+    /// 每个包携带的最大数据量,字节
+    #[arg(long)]
+    pub body_size: Option<u32>,
+    /// 重传超时,毫秒
+    #[arg(long)]
+    pub timeout: Option<u64>,
+    /// 包括包头在内的最大包长,字节。在UDP上还包括4字节的连接号
+    #[arg(long)]
+    pub max_datagram_size: Option<u32>,
+    /// 探测路径MTU,从`--body-size`开始增大每个包携带的数据量
+    #[arg(long)]
+    pub pmtu_probe: bool,
+    /// 预共享密钥文件,内容是64个十六进制字符。设置后加密并认证每个包
+    #[arg(long = "psk-file", value_name = "PATH", value_parser = psk_file)]
+    pub psk: Option<Psk>,
+    /// 接收方最多暂存的提前到达的包数,0表示直接丢弃
+    #[arg(long, default_value_t = 0)]
+    pub reorder_buffer: u32,
+}
+
+fn psk_file(path: &str) -> Result<Psk, String> {
+    Psk::from_file(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+impl ConnectionOpt {
+    /// 超出连接允许范围的参数返回`InvalidInput`
+    pub fn into_config(self) -> io::Result<Config> {
+        let mut config = Config::default();
+        if let Some(window) = self.window {
+            config.window = window;
+        }
+        if let Some(body_size) = self.body_size {
+            config.max_body_size = body_size;
+        }
+        if let Some(timeout) = self.timeout {
+            config.timeout = Duration::from_millis(timeout);
+        }
+        if let Some(max_datagram_size) = self.max_datagram_size {
+            config.max_datagram_size = max_datagram_size;
+        }
+        config.pmtu_probing = self.pmtu_probe;
+        config.psk = self.psk;
+        config.reorder_buffer = self.reorder_buffer;
+        config.validate()?;
+        Ok(config)
+    }
+}
@@ -1,146 +1,1117 @@
-use std::cmp::{min, Ordering};
-use std::collections::VecDeque;
-use std::io::Result;
+use std::cmp::min;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
 use std::sync::mpsc::Sender;
 use std::time::{Duration, Instant};
 
 use log::trace;
-use zerocopy::AsBytes;
+use rand::random;
+use serde::{Deserialize, Serialize};
+use zerocopy::{AsBytes, LayoutVerified};
 
-use super::packet::{Header, Packet};
+use super::crypto::{Psk, Seal};
+use super::packet::{FrameType, Header, Packet, PacketBuf, ParseError, Summary};
+use super::record::{Event, Recorder, Recording};
 
 pub const TIMEOUT_DURATION: Duration = Duration::from_secs(3);
+/// 延迟确认的最长等待时间,期间若有数据要发送则ACK捎带在数据包上
+pub const ACK_DELAY: Duration = Duration::from_millis(20);
 
-pub enum PacketWrapper {
-    Left(Box<[u8]>),
-    Right(Box<[u8]>),
+/// 带有发送方连接编号的包
+pub struct PacketWrapper {
+    pub id: usize,
+    pub data: PacketBuf,
 }
 
 impl PacketWrapper {
-    pub fn new(data: Box<[u8]>, is_left_side: bool) -> Self {
-        if is_left_side {
-            PacketWrapper::Left(data)
+    pub fn new(data: PacketBuf, id: usize) -> Self {
+        Self { id, data }
+    }
+    /// 同一对连接中编号为偶数的一端是左端
+    pub fn is_left_side(&self) -> bool {
+        self.id & 1 == 0
+    }
+    pub fn unwrap(self) -> PacketBuf {
+        self.data
+    }
+}
+
+/// 连接的可调参数,两端应当使用相同的配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// 发送窗口大小N,同时在途的包不超过N-1个
+    pub window: u32,
+    /// 每个包携带的最大数据量,开启探测时是探测的起点
+    pub max_body_size: u32,
+    /// 包括包头在内的最大包长,发出的包和探测都不会超过它
+    pub max_datagram_size: u32,
+    /// 是否探测路径MTU,在探测包丢失之前逐步增大每个包携带的数据量
+    pub pmtu_probing: bool,
+    /// 重传超时,也是探测的超时
+    pub timeout: Duration,
+    pub ack_delay: Duration,
+    /// 接收方最多暂存多少个期望序号之后的包,等缺口补上后一起交付。0表示和普通的GBN一样直接丢弃。
+    /// 发送方的行为不变,两端可以使用不同的值
+    #[serde(default)]
+    pub reorder_buffer: u32,
+    /// 设置后加密每个包的数据并认证整个包,两端必须使用相同的密钥。不会被序列化
+    #[serde(skip)]
+    pub psk: Option<Psk>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            window: SendSequenceSpace::N,
+            max_body_size: Connection::MAX_BODY_SIZE,
+            max_datagram_size: Connection::MAX_DATAGRAM_SIZE,
+            pmtu_probing: false,
+            timeout: TIMEOUT_DURATION,
+            ack_delay: ACK_DELAY,
+            reorder_buffer: 0,
+            psk: None,
+        }
+    }
+}
+
+impl Config {
+    /// 每个包中数据以外的字节数:包头和认证
+    pub fn overhead(&self) -> usize {
+        Header::SIZE + if self.psk.is_some() { Psk::OVERHEAD } else { 0 }
+    }
+    /// 检查`Connection::with_config`要求的取值范围,不满足时返回`InvalidInput`
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |msg: String| Err(Error::new(ErrorKind::InvalidInput, msg));
+        if self.window < 2 || self.window > 1 << 30 {
+            return invalid(format!("window must be between 2 and 2^30, got {}", self.window));
+        }
+        if self.max_body_size == 0 {
+            return invalid("max body size must be positive".to_string());
+        }
+        if self.max_datagram_size as usize <= self.overhead() {
+            return invalid(format!("max datagram size must exceed the {} byte header, got {}",
+                                   self.overhead(), self.max_datagram_size));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ConnectionStats {
+    /// 首次发送的数据包(含SYN)
+    pub packets_sent: u64,
+    /// 超时重传的包
+    pub packets_retransmitted: u64,
+    /// 首次发送的数据字节数
+    pub bytes_sent: u64,
+    pub bytes_retransmitted: u64,
+    /// 单独发送的ACK,捎带在数据包上的不算
+    pub acks_sent: u64,
+    /// 成功解析的包
+    pub packets_received: u64,
+    /// 已经接受过的重复数据包
+    pub duplicates_received: u64,
+    /// 不是期望序号而被丢弃的数据包
+    pub out_of_order_received: u64,
+    /// 不是期望序号而被暂存的数据包
+    pub reordered_received: u64,
+    /// 因无法解析而被丢弃的包
+    pub malformed_packets: u64,
+    /// 预共享密钥模式下因为认证失败或者重放而被丢弃的包
+    pub rejected_packets: u64,
+    pub probes_sent: u64,
+    /// 超时没有得到确认的探测
+    pub probes_lost: u64,
+    /// 当前每个包最多携带的数据量
+    pub body_size: u32,
+}
+
+impl ConnectionStats {
+    /// 首次发送的包占所有发出的包的比例,没有重传时为1
+    pub fn efficiency(&self) -> f64 {
+        let total = self.packets_sent + self.packets_retransmitted;
+        if total == 0 {
+            1.0
         } else {
-            PacketWrapper::Right(data)
+            self.packets_sent as f64 / total as f64
         }
     }
-    pub fn is_left_side(&self) -> bool {
-        match self {
-            PacketWrapper::Left(_) => true,
-            PacketWrapper::Right(_) => false
+}
+
+/// 某一时刻连接的全部状态,用来排查传输停滞。定时器记为距离快照时刻的剩余时间,已经到期的记为0
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionSnapshot {
+    pub id: usize,
+    pub send: SendSequenceSpace,
+    pub recv: RecvSequenceSpace,
+    pub peer_synced: bool,
+    pub timer: Option<Duration>,
+    pub ack_timer: Option<Duration>,
+    /// 已发出、等待确认的包数和它们的总长度
+    pub unacked_packets: usize,
+    pub unacked_bytes: usize,
+    pub streams: Vec<StreamSnapshot>,
+    /// 待发送的OPEN和WINDOW
+    pub pending_control: usize,
+    /// 对方打开、还没有被取走的流
+    pub accept_queue: Vec<u16>,
+    pub next_stream_id: u16,
+    pub last_stream: u16,
+    pub credit_generation: u64,
+    /// 暂存在重排缓冲区中的包
+    pub reorder_buffered: usize,
+    pub body_size: u32,
+    pub pmtu: Option<PmtuSnapshot>,
+    pub encrypted: bool,
+    /// 正在录制时已经录下的事件数
+    pub recorded_events: Option<usize>,
+    pub closed: bool,
+    pub error: Option<String>,
+    pub stats: ConnectionStats,
+    pub config: Config,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamSnapshot {
+    pub id: u16,
+    /// 收到但还没有被读出的字节数
+    pub incoming: usize,
+    /// 写入但还没有发出的字节数
+    pub unsent: usize,
+    pub send_credit: Option<u64>,
+    pub unannounced: u32,
+    pub write_closed: bool,
+    pub close_sent: bool,
+    pub read_closed: bool,
+    pub detached: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PmtuSnapshot {
+    pub confirmed: u32,
+    pub ceiling: u32,
+    pub overhead: u32,
+    /// 在途探测的编号、数据量和剩余时间
+    pub in_flight: Option<(u32, u32, Duration)>,
+    pub failures: u32,
+    pub next_id: u32,
+}
+
+/// 一条逻辑流的收发缓冲区和流量控制状态
+#[derive(Default)]
+struct Stream {
+    incoming: VecDeque<u8>,
+    unsent: VecDeque<u8>,
+    // 对方还允许这条流发送的字节数,None表示不受流量控制(默认流)
+    send_credit: Option<u64>,
+    // 已经读出但还没有通告给对方的字节数
+    unannounced: u32,
+    // 本端不再发送,`close_sent`之后CLOSE已经发出
+    write_closed: bool,
+    close_sent: bool,
+    // 对方不再发送
+    read_closed: bool,
+    // 本端已经不再读取,收到的数据直接丢弃
+    detached: bool,
+}
+
+impl Stream {
+    fn flow_controlled() -> Self {
+        Self {
+            send_credit: Some(Connection::STREAM_WINDOW as u64),
+            ..Self::default()
         }
     }
-    pub fn unwrap(self) -> Box<[u8]> {
-        match self {
-            PacketWrapper::Left(data) => data,
-            PacketWrapper::Right(data) => data
+    /// 有数据或者CLOSE可以发送
+    fn is_ready(&self) -> bool {
+        (!self.unsent.is_empty() && self.send_credit != Some(0))
+            || (self.write_closed && !self.close_sent && self.unsent.is_empty())
+    }
+    fn is_finished(&self) -> bool {
+        self.detached && self.read_closed && self.close_sent
+    }
+}
+
+/// 从基础长度向上依次探测常见的路径MTU(RFC 8899的DPLPMTUD),最后探测上界。
+/// 同一个长度连续丢失`MAX_PROBES`次,或者被本地的网络栈拒绝,就停在已经确认的长度
+struct PmtuSearch {
+    // 已经确认可以通过的数据量
+    confirmed: u32,
+    // 可能通过的最大数据量
+    ceiling: u32,
+    // 每个数据报中数据以外的字节数:首部、认证和信道的封装
+    overhead: u32,
+    // 在途的探测:编号、数据量和超时时间
+    in_flight: Option<(u32, u32, Instant)>,
+    // 当前长度已经丢失的次数
+    failures: u32,
+    next_id: u32,
+}
+
+impl PmtuSearch {
+    const MAX_PROBES: u32 = 3;
+    /// 常见的数据报长度:MTU为1280、1500和9000的链路上IPv6和IPv4的UDP载荷,以及直接承载包的链路
+    const PLATEAUS: [u32; 9] = [1232, 1252, 1280, 1452, 1472, 1500, 8952, 8972, 9000];
+
+    /// 下一个要探测的数据量,搜索结束或者已有探测在途时返回None
+    fn next_probe(&self) -> Option<u32> {
+        if self.in_flight.is_some() {
+            return None;
+        }
+        Self::PLATEAUS.iter()
+            .filter_map(|plateau| plateau.checked_sub(self.overhead))
+            .chain(std::iter::once(self.ceiling))
+            .find(|&size| size > self.confirmed && size <= self.ceiling)
+    }
+    /// 搜索到此为止,之后沿用已经确认的长度
+    fn finish(&mut self) {
+        self.ceiling = self.confirmed;
+        self.failures = 0;
+    }
+    /// 本地的网络栈直接拒绝了这个长度,更大的也不必再试
+    fn on_rejected(&mut self) {
+        self.in_flight = None;
+        self.finish();
+    }
+    fn on_lost(&mut self) {
+        self.failures += 1;
+        if self.failures >= Self::MAX_PROBES {
+            self.finish();
         }
     }
 }
 
+/// 本端打开的流编号最高位为0,对方打开的为1。发出时翻转最高位,收到时原样使用,
+/// 这样线路上的最高位总是表示流由发送方打开,两端各自分配的编号不会冲突
+#[inline]
+fn flip_stream_id(id: u16) -> u16 {
+    if id & !Connection::PEER_STREAM == 0 { id } else { id ^ Connection::PEER_STREAM }
+}
+
 pub struct Connection {
     send: SendSequenceSpace,
     recv: RecvSequenceSpace,
-    pub(crate) incoming: VecDeque<u8>,
+    // 是否已经收到对方的SYN,在此之前`recv`中的序号没有意义
+    peer_synced: bool,
     timer: Option<Instant>,
-    unacked: VecDeque<Box<[u8]>>,
-    pub(crate)unsent: VecDeque<u8>,
+    ack_timer: Option<Instant>,
+    // 与发往信道的是同一份缓冲区,重传时只增加引用计数
+    unacked: VecDeque<PacketBuf>,
+    // 编号为0的默认流总是存在,不受流量控制
+    streams: BTreeMap<u16, Stream>,
+    // 待发送的OPEN和WINDOW,先于数据发送
+    control: VecDeque<(FrameType, u16, Option<u32>)>,
+    // 对方打开、还没有被`accept_stream`取走的流
+    accepted: VecDeque<u16>,
+    next_stream_id: u16,
+    // 轮流从各条流取数据发送
+    last_stream: u16,
+    // 每次对方放宽流量控制时加一,用来唤醒等待发送的写者
+    credit_generation: u64,
+    // 按序号暂存的提前到达的包,最多`config.reorder_buffer`个
+    reorder: HashMap<u32, (FrameType, u16, Vec<u8>)>,
+    // 每个包最多携带的数据量,探测成功后增大
+    body_size: u32,
+    // 没有开启探测时为None
+    pmtu: Option<PmtuSearch>,
+    // 没有预共享密钥时为None
+    seal: Option<Seal>,
+    // 没有在录制时为None
+    recorder: Option<Recorder>,
+    // 组装新包时复用的缓冲区,避免每次发送都重新分配
+    scratch: Vec<u8>,
+    stats: ConnectionStats,
+    config: Config,
 
-    is_left_side: bool,
-    tx: Sender<PacketWrapper>,
+    id: usize,
+    // 连接关闭后置为None,信道的发送端随之释放
+    tx: Option<Sender<PacketWrapper>>,
+    // 信道或对端出错后记录下来,之后的读写都返回这个错误
+    error: Option<ErrorKind>,
 }
 
 
 impl Connection {
     pub const MAX_BODY_SIZE: u32 = 1024;
-    pub fn new(is_left_side: bool, tx: Sender<PacketWrapper>) -> Self {
+    /// 一个UDP数据报最多携带的数据
+    pub const MAX_DATAGRAM_SIZE: u32 = 65_507;
+    /// 发送缓冲区的容量,写满之后`GbnStream::write`会阻塞
+    pub const SEND_BUFFER_SIZE: usize = 64 * 1024;
+    /// 每条流初始的流量控制窗口,读出一半后通告对方
+    pub const STREAM_WINDOW: u32 = 64 * 1024;
+    /// 流编号中表示由对方打开的位
+    pub const PEER_STREAM: u16 = 1 << 15;
+    /// 以随机的初始序号新建连接,旧连接遗留在网络中的包几乎不可能落在新连接的窗口内
+    pub fn new(id: usize, tx: Sender<PacketWrapper>) -> Self {
+        Self::with_config(id, tx, Config::default(), random())
+    }
+    pub fn with_initial_seq_num(id: usize, tx: Sender<PacketWrapper>, isn: u32) -> Self {
+        Self::with_config(id, tx, Config::default(), isn)
+    }
+    pub fn with_config(id: usize, tx: Sender<PacketWrapper>, config: Config, isn: u32) -> Self {
+        if let Err(e) = config.validate() {
+            panic!("invalid config: {}", e);
+        }
+        let overhead = config.overhead();
+        let ceiling = config.max_datagram_size - overhead as u32;
+        let body_size = min(config.max_body_size, ceiling);
+        let pmtu = if config.pmtu_probing {
+            Some(PmtuSearch {
+                confirmed: body_size,
+                ceiling,
+                overhead: overhead as u32,
+                in_flight: None,
+                failures: 0,
+                next_id: 0,
+            })
+        } else {
+            None
+        };
         Self {
-            send: SendSequenceSpace::new(1),
-            recv: RecvSequenceSpace::new(1),
-            incoming: VecDeque::new(),
+            send: SendSequenceSpace::with_window(isn, config.window),
+            recv: RecvSequenceSpace::with_window(0, config.window),
+            peer_synced: false,
             timer: None,
+            ack_timer: None,
             unacked: VecDeque::new(),
-            unsent: VecDeque::new(),
-            is_left_side,
-            tx,
+            streams: vec![(0, Stream::default())].into_iter().collect(),
+            control: VecDeque::new(),
+            accepted: VecDeque::new(),
+            next_stream_id: 1,
+            last_stream: 0,
+            credit_generation: 0,
+            reorder: HashMap::new(),
+            body_size,
+            pmtu,
+            seal: config.psk.as_ref().map(Seal::new),
+            recorder: None,
+            scratch: Vec::with_capacity(Header::SIZE + body_size as usize),
+            stats: ConnectionStats { body_size, ..ConnectionStats::default() },
+            config,
+            id,
+            tx: Some(tx),
+            error: None,
         }
     }
 
     pub fn on_tick(&mut self) -> Result<()> {
-        self.send_if_could();
+        self.on_tick_at(Instant::now())
+    }
+
+    pub fn on_tick_at(&mut self, now: Instant) -> Result<()> {
+        self.record(|r| Event::Tick { at: r.at(now) });
+        if let Some(search) = &mut self.pmtu {
+            if let Some((id, size, _)) = search.in_flight.filter(|&(_, _, deadline)| deadline <= now) {
+                trace!("Connection[{}]: Probe {} of {} bytes lost", self.id, id, size);
+                search.in_flight = None;
+                search.on_lost();
+                self.stats.probes_lost += 1;
+            }
+        }
+        self.send_pending(now)?;
         if let Some(timeout) = self.timer {
-            if timeout <= Instant::now() {
-                self.reset_timer();
-                for packet in self.unacked.iter() {
-                    trace!("Connection[{}]: Resend {}", self.is_left_side as usize, Packet::parse(packet.as_ref()).unwrap().header);
-                    self.tx.send(PacketWrapper::new(packet.clone(), self.is_left_side)).expect("Send failed");
-                };
+            if timeout <= now {
+                self.reset_timer(now);
+                for i in 0..self.unacked.len() {
+                    let packet = Arc::clone(&self.unacked[i]);
+                    trace!("Connection[{}]: Resend {}", self.id, Summary(&packet));
+                    self.stats.packets_retransmitted += 1;
+                    self.stats.bytes_retransmitted += (packet.len() - Header::SIZE) as u64;
+                    self.transmit(packet)?;
+                }
             }
         }
+        if let Some(timeout) = self.ack_timer {
+            if timeout <= now {
+                self.send_ack()?;
+            }
+        }
+        Ok(())
+    }
+    #[inline]
+    fn reset_timer(&mut self, now: Instant) {
+        self.timer = Some(now + self.config.timeout);
+    }
+
+    /// 重传、延迟确认和探测定时器中最早的那个
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let probe = self.pmtu.as_ref().and_then(|search| search.in_flight).map(|(_, _, deadline)| deadline);
+        self.timer.into_iter().chain(self.ack_timer).chain(probe).min()
+    }
+
+    #[inline]
+    pub fn id(&self) -> usize {
+        self.id
+    }
+    /// 在发出第一个包之前确定编号
+    pub(crate) fn set_id(&mut self, id: usize) {
+        debug_assert!(self.stats.packets_sent == 0, "id changed after the connection sent");
+        self.id = id;
+    }
+    #[inline]
+    pub fn send_space(&self) -> &SendSequenceSpace {
+        &self.send
+    }
+    #[inline]
+    pub fn recv_space(&self) -> &RecvSequenceSpace {
+        &self.recv
+    }
+    #[inline]
+    pub fn stats(&self) -> &ConnectionStats {
+        &self.stats
+    }
+    #[inline]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+    /// 当前每个包最多携带的数据量
+    #[inline]
+    pub fn body_size(&self) -> u32 {
+        self.body_size
+    }
+    #[inline]
+    pub fn unsent_len(&self) -> usize {
+        self.streams.values().map(|stream| stream.unsent.len()).sum()
+    }
+
+    /// 关闭连接: 丢弃所有未发送和未确认的数据,停止全部定时器并释放信道的发送端。
+    /// 已经收到的数据仍然可以读出。
+    pub fn close(&mut self) {
+        self.record(|_| Event::Close);
+        trace!("Connection[{}]: Closed", self.id);
+        self.tx = None;
+        self.timer = None;
+        self.ack_timer = None;
+        self.unacked.clear();
+        self.control.clear();
+        self.reorder.clear();
+        if let Some(search) = &mut self.pmtu {
+            search.in_flight = None;
+        }
+        for stream in self.streams.values_mut() {
+            stream.unsent.clear();
+        }
+    }
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_none()
+    }
+    /// 因为信道或对端的故障而关闭连接,之后`check`会返回`kind`类型的错误
+    pub fn fail(&mut self, kind: ErrorKind) {
+        trace!("Connection[{}]: Failed: {:?}", self.id, kind);
+        if self.error.is_none() {
+            self.error = Some(kind);
+        }
+        self.close();
+    }
+    pub fn snapshot(&self) -> ConnectionSnapshot {
+        self.snapshot_at(Instant::now())
+    }
+    pub fn snapshot_at(&self, now: Instant) -> ConnectionSnapshot {
+        let remaining = |deadline: Instant| deadline.saturating_duration_since(now);
+        ConnectionSnapshot {
+            id: self.id,
+            send: self.send.clone(),
+            recv: self.recv.clone(),
+            peer_synced: self.peer_synced,
+            timer: self.timer.map(remaining),
+            ack_timer: self.ack_timer.map(remaining),
+            unacked_packets: self.unacked.len(),
+            unacked_bytes: self.unacked.iter().map(|packet| packet.len()).sum(),
+            streams: self.streams.iter().map(|(&id, stream)| StreamSnapshot {
+                id,
+                incoming: stream.incoming.len(),
+                unsent: stream.unsent.len(),
+                send_credit: stream.send_credit,
+                unannounced: stream.unannounced,
+                write_closed: stream.write_closed,
+                close_sent: stream.close_sent,
+                read_closed: stream.read_closed,
+                detached: stream.detached,
+            }).collect(),
+            pending_control: self.control.len(),
+            accept_queue: self.accepted.iter().copied().collect(),
+            next_stream_id: self.next_stream_id,
+            last_stream: self.last_stream,
+            credit_generation: self.credit_generation,
+            reorder_buffered: self.reorder.len(),
+            body_size: self.body_size,
+            pmtu: self.pmtu.as_ref().map(|search| PmtuSnapshot {
+                confirmed: search.confirmed,
+                ceiling: search.ceiling,
+                overhead: search.overhead,
+                in_flight: search.in_flight.map(|(id, size, deadline)| (id, size, remaining(deadline))),
+                failures: search.failures,
+                next_id: search.next_id,
+            }),
+            encrypted: self.seal.is_some(),
+            recorded_events: self.recorder.as_ref().map(Recorder::len),
+            closed: self.is_closed(),
+            error: self.error.map(|kind| format!("{:?}", kind)),
+            stats: self.stats.clone(),
+            config: self.config.clone(),
+        }
+    }
+    /// 连接出过错时返回对应的错误
+    pub fn check(&self) -> Result<()> {
+        match self.error {
+            Some(ErrorKind::ConnectionReset) => Err(Error::new(ErrorKind::ConnectionReset, "connection reset by peer")),
+            Some(kind) => Err(Error::new(kind, "packet channel disconnected")),
+            None => Ok(()),
+        }
+    }
+    /// 所有数据都已发出并被对方确认
+    #[inline]
+    pub fn is_idle(&self) -> bool {
+        self.unacked.is_empty() && self.control.is_empty()
+            && self.streams.values().all(|stream| stream.unsent.is_empty() && (stream.close_sent || !stream.write_closed))
+    }
+
+    /// 已发送但尚未被确认的包数
+    pub fn unacked_len(&self) -> usize {
+        self.unacked.len()
+    }
+
+    /// 把待发送的数据追加到发送缓冲区,返回实际接受的字节数
+    pub fn enqueue(&mut self, buf: &[u8]) -> usize {
+        self.enqueue_on(0, buf)
+    }
+    /// 把已按序收到的数据读到`buf`中,返回读出的字节数
+    pub fn read_incoming(&mut self, buf: &mut [u8]) -> usize {
+        self.read_from(0, buf)
+    }
+    #[inline]
+    pub fn has_incoming(&self) -> bool {
+        self.stream_has_incoming(0)
+    }
+    #[inline]
+    pub fn send_buffer_space(&self) -> usize {
+        self.stream_send_space(0)
+    }
+
+    /// 打开一条新的流,对方可以通过`accept_stream`得到它。编号用完时返回None
+    pub fn open_stream(&mut self) -> Option<u16> {
+        self.record(|_| Event::OpenStream);
+        let id = if self.next_stream_id >= Self::PEER_STREAM {
+            None
+        } else {
+            let id = self.next_stream_id;
+            self.next_stream_id += 1;
+            self.streams.insert(id, Stream::flow_controlled());
+            self.control.push_back((FrameType::Open, id, None));
+            Some(id)
+        };
+        self.record(|_| Event::StreamId { stream: id });
+        id
+    }
+    /// 取出对方打开的下一条流
+    pub fn accept_stream(&mut self) -> Option<u16> {
+        self.record(|_| Event::AcceptStream);
+        let id = self.accepted.pop_front();
+        self.record(|_| Event::StreamId { stream: id });
+        id
+    }
+    /// 本端不再读写这条流:剩余的数据发完后发送CLOSE,之后收到的数据都被丢弃
+    pub fn close_stream(&mut self, id: u16) {
+        self.record(|_| Event::CloseStream { stream: id });
+        if id == 0 {
+            return;
+        }
+        self.stop_writing(id);
+        self.stop_reading(id);
+        self.remove_if_finished(id);
+    }
+    /// 本端不再向这条流写入,剩余的数据发完后发送CLOSE。默认流的CLOSE就是整个连接的FIN
+    pub fn shutdown_write(&mut self, id: u16) {
+        self.record(|_| Event::ShutdownWrite { stream: id });
+        self.stop_writing(id);
+    }
+    /// 本端不再读取这条流,已经收到和之后收到的数据都被丢弃
+    pub fn shutdown_read(&mut self, id: u16) {
+        self.record(|_| Event::ShutdownRead { stream: id });
+        self.stop_reading(id);
+    }
+    fn stop_writing(&mut self, id: u16) {
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.write_closed = true;
+        }
+    }
+    fn stop_reading(&mut self, id: u16) {
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.detached = true;
+            let discarded = stream.incoming.len() as u32;
+            stream.incoming.clear();
+            self.grant(id, discarded);
+        }
+    }
+    #[inline]
+    pub fn is_write_closed(&self, id: u16) -> bool {
+        self.streams.get(&id).is_none_or(|stream| stream.write_closed)
+    }
+    fn remove_if_finished(&mut self, id: u16) {
+        if id != 0 && self.streams.get(&id).is_some_and(Stream::is_finished) {
+            trace!("Connection[{}]: Stream {} finished", self.id, id);
+            self.streams.remove(&id);
+        }
+    }
+    /// 对方已经关闭这条流而且数据都已读出,或者本端不再读取
+    pub fn stream_is_eof(&self, id: u16) -> bool {
+        self.streams.get(&id).is_none_or(|stream| stream.detached || (stream.read_closed && stream.incoming.is_empty()))
+    }
+    pub fn enqueue_on(&mut self, id: u16, buf: &[u8]) -> usize {
+        self.record(|_| Event::Write { stream: id, bytes: buf.to_vec() });
+        let n = min(buf.len(), self.stream_send_space(id));
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.unsent.extend(buf[..n].iter());
+        }
+        self.record(|_| Event::Enqueued { len: n });
+        n
+    }
+    pub fn read_from(&mut self, id: u16, buf: &mut [u8]) -> usize {
+        self.record(|_| Event::Read { stream: id, len: buf.len() });
+        let n = self.read_stream(id, buf);
+        self.record(|_| Event::Delivered { bytes: buf[..n].to_vec() });
+        n
+    }
+    fn read_stream(&mut self, id: u16, buf: &mut [u8]) -> usize {
+        let stream = match self.streams.get_mut(&id) {
+            Some(stream) => stream,
+            None => return 0,
+        };
+        let mut nread = 0;
+        let (head, tail) = stream.incoming.as_slices();
+        let hread = min(buf.len(), head.len());
+        buf[..hread].copy_from_slice(&head[..hread]);
+        nread += hread;
+        let tread = min(buf.len() - nread, tail.len());
+        buf[hread..(hread + tread)].copy_from_slice(&tail[..tread]);
+        nread += tread;
+        drop(stream.incoming.drain(..nread));
+        self.grant(id, nread as u32);
+        nread
+    }
+    /// 读出的数据腾出了接收缓冲区,累计到窗口的一半时通告对方
+    fn grant(&mut self, id: u16, n: u32) {
+        let stream = match self.streams.get_mut(&id) {
+            Some(stream) if stream.send_credit.is_some() && !stream.read_closed => stream,
+            _ => return,
+        };
+        stream.unannounced += n;
+        if stream.unannounced >= Self::STREAM_WINDOW / 2 {
+            self.control.push_back((FrameType::Window, id, Some(stream.unannounced)));
+            stream.unannounced = 0;
+        }
+    }
+    #[inline]
+    pub fn stream_has_incoming(&self, id: u16) -> bool {
+        self.streams.get(&id).is_some_and(|stream| !stream.incoming.is_empty())
+    }
+    /// 流的发送缓冲区还能接受的字节数,流不存在或者已经关闭时为0
+    #[inline]
+    pub fn stream_send_space(&self, id: u16) -> usize {
+        match self.streams.get(&id) {
+            Some(stream) if !stream.write_closed => Self::SEND_BUFFER_SIZE.saturating_sub(stream.unsent.len()),
+            _ => 0,
+        }
+    }
+    /// 有数据可读、有流关闭或者有新的流等待接受
+    pub fn has_readable(&self) -> bool {
+        !self.accepted.is_empty()
+            || self.streams.values().any(|stream| !stream.incoming.is_empty() || stream.read_closed)
+    }
+    /// 对方每放宽一次流量控制加一
+    #[inline]
+    pub fn credit_generation(&self) -> u64 {
+        self.credit_generation
+    }
+
+    /// 开始录制连接的输入和输出,时间从`epoch`算起。只能在连接发出第一个包之前开始。
+    /// 预共享密钥模式下录下的是解密后的包,通不过认证的包不录,重放时不加密
+    pub fn start_recording(&mut self, epoch: Instant) {
+        assert!(self.stats.packets_sent == 0, "recording must start before the connection sends");
+        self.recorder = Some(Recorder::new(epoch, self.send.next_seq_num));
+    }
+    /// 停止录制,返回到目前为止的记录
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        self.recorder.take().map(Recorder::finish)
+    }
+    #[inline]
+    fn record<F: FnOnce(&Recorder) -> Event>(&mut self, event: F) {
+        if let Some(recorder) = &mut self.recorder {
+            let event = event(recorder);
+            recorder.push(event);
+        }
+    }
+
+    /// 发送SYN,告知对方本端的初始序号。SYN占用一个序号,和数据包一样等待确认、超时重传
+    pub fn connect(&mut self) -> Result<()> {
+        self.connect_at(Instant::now())
+    }
+    pub fn connect_at(&mut self, now: Instant) -> Result<()> {
+        self.record(|r| Event::Connect { at: r.at(now) });
+        let header = Header::new_syn(self.send.get_next_seq_num_then_inc());
+        trace!("Connection[{}]: Send {}", self.id, header);
+        let packet = PacketBuf::from(header.as_bytes());
+        self.stats.packets_sent += 1;
+        self.transmit(Arc::clone(&packet))?;
+        self.unacked.push_back(packet);
+        self.reset_timer(now);
         Ok(())
     }
     #[inline]
-    fn reset_timer(&mut self) {
-        self.timer = Some(Instant::now() + TIMEOUT_DURATION);
+    pub fn is_peer_synced(&self) -> bool {
+        self.peer_synced
+    }
+
+    #[inline]
+    fn cumulative_ack_num(&self) -> u32 {
+        self.recv.expected_seq_num.wrapping_sub(1)
+    }
+
+    /// 把包交给信道。信道已断开时连接随之失效,错误同时被记录下来。
+    /// 有预共享密钥时每次发送(包括重传)都重新加密
+    fn transmit(&mut self, packet: PacketBuf) -> Result<()> {
+        self.record(|_| Event::Sent { bytes: packet.to_vec() });
+        let packet = match &mut self.seal {
+            Some(seal) => seal.seal(&packet),
+            None => packet,
+        };
+        let sent = match &self.tx {
+            Some(tx) => tx.send(PacketWrapper::new(packet, self.id)).is_ok(),
+            None => return self.check(),
+        };
+        if !sent {
+            self.fail(ErrorKind::BrokenPipe);
+        }
+        self.check()
+    }
+
+    fn send_ack(&mut self) -> Result<()> {
+        self.ack_timer = None;
+        let ack_header = Header::new(self.send.next_seq_num, self.cumulative_ack_num(), 0, self.peer_synced);
+        trace!("Connection[{}]: Send {}", self.id, ack_header);
+        self.stats.acks_sent += 1;
+        self.transmit(PacketBuf::from(ack_header.as_bytes()))
     }
 
-    pub fn send_if_could(&mut self) {
-        if self.send.is_sendable() && !self.unsent.is_empty() {
-            let body_len = min(Self::MAX_BODY_SIZE as usize, self.unsent.len());
-            let header = Header::new(self.send.get_next_seq_num_then_inc(), body_len as u32, false);
-            trace!("Connection[{}]: Send {}", self.is_left_side as usize, header);
-            let packet = header.as_bytes().iter().copied().chain(self.unsent.drain(..body_len)).collect::<Box<_>>();
-            self.tx.send(PacketWrapper::new(packet.clone(), self.is_left_side)).unwrap();
+    pub fn send_if_could(&mut self) -> Result<()> {
+        self.send_if_could_at(Instant::now())
+    }
+    pub fn send_if_could_at(&mut self, now: Instant) -> Result<()> {
+        self.record(|r| Event::Flush { at: r.at(now) });
+        self.send_pending(now)
+    }
+    fn send_pending(&mut self, now: Instant) -> Result<()> {
+        if self.is_closed() {
+            return self.check();
+        }
+        while self.send.is_sendable() {
+            self.scratch.clear();
+            let (frame_type, id) = if let Some((frame_type, id, value)) = self.control.pop_front() {
+                if let Some(value) = value {
+                    self.scratch.extend_from_slice(&value.to_be_bytes());
+                }
+                (frame_type, id)
+            } else if let Some(id) = self.next_ready_stream() {
+                let stream = self.streams.get_mut(&id).unwrap();
+                if stream.unsent.is_empty() {
+                    stream.close_sent = true;
+                    (FrameType::Close, id)
+                } else {
+                    let credit = stream.send_credit.unwrap_or(u64::MAX);
+                    let body_len = min(min(self.body_size as usize, stream.unsent.len()) as u64, credit) as usize;
+                    if let Some(credit) = &mut stream.send_credit {
+                        *credit -= body_len as u64;
+                    }
+                    self.scratch.extend(stream.unsent.drain(..body_len));
+                    self.stats.bytes_sent += body_len as u64;
+                    (FrameType::Data, id)
+                }
+            } else {
+                break;
+            };
+            self.last_stream = id;
+            // 数据包总是捎带当前的累计确认,待发送的延迟ACK随之取消
+            self.ack_timer = None;
+            let header = Header::new_frame(self.send.get_next_seq_num_then_inc(), self.cumulative_ack_num(), frame_type,
+                                           flip_stream_id(id), self.scratch.len() as u32, self.peer_synced);
+            trace!("Connection[{}]: Send {}", self.id, header);
+            self.scratch.splice(0..0, header.as_bytes().iter().copied());
+            let packet = PacketBuf::from(self.scratch.as_slice());
+            self.stats.packets_sent += 1;
+            self.transmit(Arc::clone(&packet))?;
             self.unacked.push_back(packet);
-            self.reset_timer();
+            self.reset_timer(now);
+            if frame_type == FrameType::Close {
+                self.remove_if_finished(id);
+            }
+        }
+        self.probe_if_due(now)
+    }
+
+    /// 对方同步之后,没有探测在途时发出下一个探测。探测不占用序号,数据是全零的填充
+    fn probe_if_due(&mut self, now: Instant) -> Result<()> {
+        let search = match &mut self.pmtu {
+            Some(search) if self.peer_synced => search,
+            _ => return Ok(()),
+        };
+        let size = match search.next_probe() {
+            Some(size) => size,
+            None => return Ok(()),
+        };
+        let id = search.next_id;
+        search.next_id = id.wrapping_add(1);
+        search.in_flight = Some((id, size, now + self.config.timeout));
+        let header = Header::new_frame(id, self.cumulative_ack_num(), FrameType::Probe, 0, size, self.peer_synced);
+        trace!("Connection[{}]: Send {}", self.id, header);
+        let mut packet = Vec::with_capacity(Header::SIZE + size as usize);
+        packet.extend_from_slice(header.as_bytes());
+        packet.resize(Header::SIZE + size as usize, 0);
+        self.stats.probes_sent += 1;
+        self.transmit(PacketBuf::from(packet.as_slice()))
+    }
+
+    /// 探测通过了路径,之后的包可以携带这么多数据
+    fn on_probe_ack(&mut self, id: u32, body: &[u8]) {
+        let size = match <[u8; 4]>::try_from(body) {
+            Ok(size) => u32::from_be_bytes(size),
+            Err(_) => return,
+        };
+        let search = match &mut self.pmtu {
+            Some(search) if search.in_flight.is_some_and(|(sent, probed, _)| sent == id && probed == size) => search,
+            _ => return,
+        };
+        search.in_flight = None;
+        search.failures = 0;
+        search.confirmed = size;
+        trace!("Connection[{}]: Probe {} of {} bytes acknowledged", self.id, id, size);
+        self.body_size = size;
+        self.stats.body_size = size;
+    }
+
+    /// 信道在每个包外面另加的字节数,探测的长度按加上它之后的数据报长度选取
+    pub(crate) fn set_encapsulation(&mut self, bytes: u32) {
+        if let Some(search) = &mut self.pmtu {
+            search.overhead += bytes;
         }
     }
 
-    pub fn on_packet(&mut self, packet: Box<[u8]>) {
-        if let Some(packet) = Packet::parse(packet.as_ref()) {
-            trace!("Connection[{}]: Recv {}", self.is_left_side as usize, packet.header);
-            if packet.is_ack() {
-                let acked_count = self.send.ack(packet.get_seq_num());
-                drop(self.unacked.drain(..acked_count));
-                if acked_count != 0 {
-                    self.reset_timer();
+    /// 信道因为数据报超过已知的路径MTU(EMSGSIZE)而没能发出`packet`。
+    /// 如果它是在途的探测,立即结束搜索,不必等到超时
+    pub fn on_datagram_too_large(&mut self, packet: &[u8]) {
+        let header = match LayoutVerified::<_, Header>::new_unaligned_from_prefix(packet) {
+            Some((header, _)) => header,
+            None => return,
+        };
+        if header.get_frame_type() != FrameType::Probe {
+            return;
+        }
+        let id = header.seq_num.get();
+        if let Some(search) = &mut self.pmtu {
+            if let Some((_, size, _)) = search.in_flight.filter(|&(sent, _, _)| sent == id) {
+                trace!("Connection[{}]: Probe {} of {} bytes too large to send", self.id, id, size);
+                search.on_rejected();
+                self.stats.probes_lost += 1;
+            }
+        }
+    }
+
+    /// 从上一次发送的流之后开始,找下一条有东西要发送的流
+    fn next_ready_stream(&self) -> Option<u16> {
+        let after = self.streams.range(self.last_stream.wrapping_add(1)..);
+        let before = self.streams.range(..=self.last_stream);
+        after.chain(before)
+            .find(|(_, stream)| stream.is_ready())
+            .map(|(id, _)| *id)
+    }
+
+    /// 处理按序收到的数据包或控制包
+    fn on_frame(&mut self, frame_type: FrameType, id: u16, body: &[u8]) {
+        if frame_type == FrameType::Open {
+            if id & Self::PEER_STREAM != 0 && !self.streams.contains_key(&id) {
+                self.streams.insert(id, Stream::flow_controlled());
+                self.accepted.push_back(id);
+            }
+            return;
+        }
+        let stream = match self.streams.get_mut(&id) {
+            // 默认流不受流量控制
+            Some(_) if id == 0 && frame_type == FrameType::Window => {
+                trace!("Connection[{}]: Ignore {:?} for the default stream", self.id, frame_type);
+                return;
+            }
+            Some(stream) => stream,
+            None => {
+                trace!("Connection[{}]: {:?} for unknown stream {}", self.id, frame_type, id);
+                return;
+            }
+        };
+        match frame_type {
+            FrameType::Data if stream.detached => {
+                let n = body.len() as u32;
+                self.grant(id, n);
+            }
+            FrameType::Data => stream.incoming.extend(body.iter()),
+            FrameType::Window => {
+                if let (Some(credit), Ok(increment)) = (&mut stream.send_credit, <[u8; 4]>::try_from(body)) {
+                    *credit += u32::from_be_bytes(increment) as u64;
+                    self.credit_generation += 1;
                 }
-            } else if let Some(is_fresh) = self.recv.rcv(packet.get_seq_num()) {
-                let ack_header = Header::new(packet.get_seq_num(), 0, true);
-                trace!("Connection[{}]: Send {}", self.is_left_side as usize, ack_header);
-                let ack_packet = PacketWrapper::new(ack_header.as_bytes().iter().copied().collect::<Box<_>>(), self.is_left_side);
-                self.tx.send(ack_packet).expect("Send ACK failed");
-                if is_fresh {
-                    self.incoming.extend(packet.body.iter().take(packet.get_body_len() as usize));
+            }
+            FrameType::Close => {
+                stream.read_closed = true;
+                self.remove_if_finished(id);
+            }
+            FrameType::Open | FrameType::Probe | FrameType::ProbeAck => unreachable!(),
+        }
+    }
+
+    /// 处理收到的包。无法解析的包会被计数并丢弃,连接状态不受影响。
+    /// 回复时信道出错不在这里返回,而是记录在连接上,由`check`报告
+    pub fn on_packet(&mut self, packet: &[u8]) -> std::result::Result<(), ParseError> {
+        self.on_packet_at(packet, Instant::now())
+    }
+    pub fn on_packet_at(&mut self, packet: &[u8], now: Instant) -> std::result::Result<(), ParseError> {
+        if self.is_closed() {
+            self.record(|r| Event::Packet { at: r.at(now), bytes: packet.to_vec() });
+            return Ok(());
+        }
+        let opened;
+        let packet = match &mut self.seal {
+            Some(seal) => match seal.open(packet) {
+                Ok(plain) => {
+                    opened = plain;
+                    opened.as_slice()
+                }
+                Err(e) => {
+                    self.stats.rejected_packets += 1;
+                    trace!("Connection[{}]: Drop packet: {}", self.id, e);
+                    return Err(e);
                 }
+            },
+            None => packet,
+        };
+        self.record(|r| Event::Packet { at: r.at(now), bytes: packet.to_vec() });
+        let packet = match Packet::parse(packet) {
+            Ok(packet) => packet,
+            Err(e) => {
+                self.stats.malformed_packets += 1;
+                trace!("Connection[{}]: Drop malformed packet: {}", self.id, e);
+                return Err(e);
             }
+        };
+        trace!("Connection[{}]: Recv {}", self.id, packet.header);
+        self.stats.packets_received += 1;
+        if packet.is_ack() {
+            let acked_count = self.send.ack(packet.get_ack_num());
+            drop(self.unacked.drain(..acked_count));
+            if acked_count != 0 {
+                if self.send.has_unacked() {
+                    self.reset_timer(now);
+                } else {
+                    self.timer = None;
+                }
+                // 窗口向前滑动了,继续发送缓冲区中的数据
+                let _ = self.send_pending(now);
+            }
+        }
+        match packet.get_frame_type() {
+            FrameType::Probe => {
+                let header = Header::new_frame(packet.get_seq_num(), self.cumulative_ack_num(), FrameType::ProbeAck, 0, 4, self.peer_synced);
+                let mut reply = header.as_bytes().to_vec();
+                reply.extend_from_slice(&packet.get_body_len().to_be_bytes());
+                let _ = self.transmit(PacketBuf::from(reply.as_slice()));
+                return Ok(());
+            }
+            FrameType::ProbeAck => {
+                self.on_probe_ack(packet.get_seq_num(), packet.body);
+                return Ok(());
+            }
+            _ => {}
         }
+        if packet.is_syn() {
+            if !self.peer_synced {
+                self.recv = RecvSequenceSpace::with_window(packet.get_seq_num().wrapping_add(1), self.config.window);
+                self.peer_synced = true;
+                let _ = self.send_ack();
+            } else if packet.get_seq_num() == self.cumulative_ack_num() {
+                // 对方没收到对SYN的确认,重新确认
+                let _ = self.send_ack();
+            } else {
+                trace!("Connection[{}]: Ignore stale {}", self.id, packet.header);
+            }
+        } else if packet.is_sequenced() && self.peer_synced {
+            let seq_num = packet.get_seq_num();
+            match self.recv.rcv(seq_num) {
+                Some(true) => {
+                    self.on_frame(packet.get_frame_type(), packet.get_stream_id(), packet.body);
+                    // 缺口补上了,依次交付紧接着的暂存包
+                    while let Some((frame_type, id, body)) = self.reorder.remove(&self.recv.expected_seq_num) {
+                        self.recv.rcv(self.recv.expected_seq_num);
+                        self.on_frame(frame_type, id, &body);
+                    }
+                    if self.ack_timer.is_none() {
+                        self.ack_timer = Some(now + self.config.ack_delay);
+                    }
+                }
+                // 重复的包说明对方的ACK可能丢失了,立即重新确认
+                Some(false) => {
+                    self.stats.duplicates_received += 1;
+                    let _ = self.send_ack();
+                }
+                None => {
+                    // 只暂存对方窗口内的包,更远的包不可能是这个连接现在发出的
+                    let ahead = seq_num.wrapping_sub(self.recv.expected_seq_num);
+                    if ahead <= self.config.reorder_buffer && ahead < self.recv.window {
+                        if self.reorder.contains_key(&seq_num) {
+                            self.stats.duplicates_received += 1;
+                        } else {
+                            self.stats.reordered_received += 1;
+                            self.reorder.insert(seq_num, (packet.get_frame_type(), packet.get_stream_id(), packet.body.to_vec()));
+                        }
+                    } else {
+                        self.stats.out_of_order_received += 1;
+                    }
+                }
+            }
+        }
+        Ok(())
     }
 }
 
 
+#[derive(Debug, Clone, Serialize)]
 pub struct SendSequenceSpace {
     // 最早的未确认分组的序号
     pub base: u32,
     pub next_seq_num: u32, // 最小的未使用序号
+    pub window: u32,
 }
 
 impl SendSequenceSpace {
-    const N: u32 = 32;
+    pub const N: u32 = 32;
     #[inline]
     pub fn new(base: u32) -> Self {
+        Self::with_window(base, Self::N)
+    }
+    #[inline]
+    pub fn with_window(base: u32, window: u32) -> Self {
         Self {
             base,
             next_seq_num: base,
+            window,
         }
     }
     #[inline]
     pub fn is_sendable(&self) -> bool {
-        wrapping_lt(self.next_seq_num, self.base.wrapping_add(Self::N - 1))
+        wrapping_lt(self.next_seq_num, self.base.wrapping_add(self.window - 1))
     }
     #[inline]
     pub fn ack(&mut self, seq_num: u32) -> usize {
-        if wrapping_lt(self.base, seq_num) {
+        // 累计确认: 只接受落在[base, next_seq_num)内的确认号
+        if wrapping_lt(seq_num, self.base) || !wrapping_lt(seq_num, self.next_seq_num) {
             0
         } else {
             let acked_count = seq_num.wrapping_sub(self.base).wrapping_add(1);
@@ -164,27 +1135,37 @@ impl SendSequenceSpace {
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
 pub struct RecvSequenceSpace {
-    pub expected_seq_num: u32
+    pub expected_seq_num: u32,
+    // 与发送方的窗口大小相同,用来区分重复包和旧连接遗留的包
+    pub window: u32,
 }
 
 impl RecvSequenceSpace {
     #[inline]
     pub fn new(expected_seq_num: u32) -> Self {
+        Self::with_window(expected_seq_num, SendSequenceSpace::N)
+    }
+    #[inline]
+    pub fn with_window(expected_seq_num: u32, window: u32) -> Self {
         Self {
-            expected_seq_num
+            expected_seq_num,
+            window,
         }
     }
-    /// 返回值None代表当前无法接受的包,Some(false)代表已经接受过的包
+    /// 返回值None代表当前无法接受的包,Some(false)代表已经接受过的包。
+    /// 只有落在期望序号之前一个窗口内的包才算重复包,更早的包视为旧连接遗留的包
     #[inline]
     pub fn rcv(&mut self, seq_num: u32) -> Option<bool> {
-        match self.expected_seq_num.cmp(&seq_num) {
-            Ordering::Equal => {
-                self.expected_seq_num += 1;
-                Some(true)
-            }
-            Ordering::Greater => Some(false),
-            Ordering::Less => None
+        if seq_num == self.expected_seq_num {
+            self.expected_seq_num = self.expected_seq_num.wrapping_add(1);
+            Some(true)
+        } else if wrapping_lt(seq_num, self.expected_seq_num)
+            && self.expected_seq_num.wrapping_sub(seq_num) <= self.window {
+            Some(false)
+        } else {
+            None
         }
     }
 }
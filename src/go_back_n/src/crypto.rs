@@ -0,0 +1,204 @@
+//! 预共享密钥模式下对包的加密和认证
+//!
+//! 包头保持明文,作为附加数据参与认证,后面依次是24字节的nonce、加密的数据和16字节的标签。
+//! nonce由发送方随机选择的16字节前缀和每发一个包加一的64位计数器组成,
+//! 重传的包也会用新的计数器重新加密,所以同一个计数器出现两次就是重放。
+//!
+//! 防重放窗口以这个计数器而不是包的序号为键:GBN的重传会合法地重复同一个序号,
+//! ACK和探测又不占用序号,以序号为键就既要放过重传又挡不住重放的ACK。
+//! 计数器对每次发送都是唯一的,而且本来就是AEAD要求的不重复的nonce。
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use chacha20poly1305::{AeadInPlace, KeyInit, Tag, XChaCha20Poly1305, XNonce};
+use rand::random;
+
+use super::packet::{Header, PacketBuf, ParseError};
+
+const PREFIX_SIZE: usize = 16;
+const NONCE_SIZE: usize = PREFIX_SIZE + 8;
+const TAG_SIZE: usize = 16;
+// 接收方记住最近这么多个计数器,更早的包一律当作重放
+const REPLAY_WINDOW: u64 = 1024;
+const WORDS: usize = (REPLAY_WINDOW / 64) as usize;
+
+/// 两端事先共享的32字节密钥
+#[derive(Clone)]
+pub struct Psk([u8; 32]);
+
+impl Psk {
+    /// 每个包因为加密而增加的长度
+    pub const OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+    /// 64个十六进制字符
+    pub fn from_hex(s: &str) -> Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, "key must be 64 hex digits");
+        let s = s.trim();
+        if s.len() != 64 || !s.is_ascii() {
+            return Err(invalid());
+        }
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(key))
+    }
+    /// 文件中是64个十六进制字符
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_hex(&fs::read_to_string(path)?)
+    }
+}
+
+/// 不在日志中泄露密钥
+impl fmt::Debug for Psk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Psk(..)")
+    }
+}
+
+/// 最近收到的计数器,和IPsec的防重放窗口一样用位图记录
+struct ReplayWindow {
+    highest: u64,
+    bits: [u64; WORDS],
+}
+
+impl ReplayWindow {
+    fn bit(counter: u64) -> (usize, u64) {
+        ((counter / 64) as usize % WORDS, 1 << (counter % 64))
+    }
+    fn is_fresh(&self, counter: u64) -> bool {
+        if counter > self.highest {
+            return true;
+        }
+        if self.highest - counter >= REPLAY_WINDOW {
+            return false;
+        }
+        let (word, mask) = Self::bit(counter);
+        self.bits[word] & mask == 0
+    }
+    /// 只在认证通过后调用,伪造的包不能推动窗口
+    fn mark(&mut self, counter: u64) {
+        if counter > self.highest {
+            if counter - self.highest >= REPLAY_WINDOW {
+                self.bits = [0; WORDS];
+            } else {
+                for skipped in self.highest + 1..counter {
+                    let (word, mask) = Self::bit(skipped);
+                    self.bits[word] &= !mask;
+                }
+            }
+            self.highest = counter;
+        }
+        let (word, mask) = Self::bit(counter);
+        self.bits[word] |= mask;
+    }
+}
+
+/// 最近见过的nonce。新连接的SYN还没有连接的防重放窗口可用,靠它拒绝重放的SYN
+#[derive(Default)]
+pub(crate) struct NonceHistory {
+    seen: HashSet<[u8; NONCE_SIZE]>,
+    order: VecDeque<[u8; NONCE_SIZE]>,
+}
+
+impl NonceHistory {
+    /// 只记住这么多个,更早的nonce被忘掉
+    const CAPACITY: usize = REPLAY_WINDOW as usize;
+
+    /// `packet`必须已经通过认证。第一次见到它的nonce时返回true
+    pub fn insert(&mut self, packet: &[u8]) -> bool {
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&packet[Header::SIZE..Header::SIZE + NONCE_SIZE]);
+        if !self.seen.insert(nonce) {
+            return false;
+        }
+        self.order.push_back(nonce);
+        if self.order.len() > Self::CAPACITY {
+            let oldest = self.order.pop_front().unwrap();
+            self.seen.remove(&oldest);
+        }
+        true
+    }
+}
+
+/// 一个连接的加密状态
+pub(crate) struct Seal {
+    cipher: XChaCha20Poly1305,
+    prefix: [u8; PREFIX_SIZE],
+    counter: u64,
+    // 对方的前缀,由第一个通过认证的包确定,之后其他前缀的包(比如旧连接的包)都被拒绝
+    peer_prefix: Option<[u8; PREFIX_SIZE]>,
+    replay: ReplayWindow,
+}
+
+impl Seal {
+    pub fn new(psk: &Psk) -> Self {
+        let mut replay = ReplayWindow { highest: 0, bits: [0; WORDS] };
+        // 计数器从1开始,0永远不会被接受
+        replay.mark(0);
+        Self {
+            cipher: XChaCha20Poly1305::new(&psk.0.into()),
+            prefix: random(),
+            counter: 0,
+            peer_prefix: None,
+            replay,
+        }
+    }
+
+    /// 加密明文的包,每次调用都使用新的nonce
+    pub fn seal(&mut self, packet: &[u8]) -> PacketBuf {
+        self.counter += 1;
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce[..PREFIX_SIZE].copy_from_slice(&self.prefix);
+        nonce[PREFIX_SIZE..].copy_from_slice(&self.counter.to_be_bytes());
+        let (header, body) = packet.split_at(Header::SIZE.min(packet.len()));
+        let mut sealed = Vec::with_capacity(packet.len() + Psk::OVERHEAD);
+        sealed.extend_from_slice(header);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(body);
+        let tag = self.cipher
+            .encrypt_in_place_detached(XNonce::from_slice(&nonce), header, &mut sealed[header.len() + NONCE_SIZE..])
+            .expect("packet too long to encrypt");
+        sealed.extend_from_slice(&tag);
+        PacketBuf::from(sealed.as_slice())
+    }
+
+    /// 认证并解密收到的包,返回明文的包。伪造、篡改和重放的包被拒绝,状态不受影响
+    pub fn open(&mut self, packet: &[u8]) -> std::result::Result<Vec<u8>, ParseError> {
+        if packet.len() < Header::SIZE + Psk::OVERHEAD {
+            return Err(ParseError::Forged);
+        }
+        let (header, rest) = packet.split_at(Header::SIZE);
+        let (nonce, rest) = rest.split_at(NONCE_SIZE);
+        let (body, tag) = rest.split_at(rest.len() - TAG_SIZE);
+        let mut counter = [0u8; 8];
+        counter.copy_from_slice(&nonce[PREFIX_SIZE..]);
+        let counter = u64::from_be_bytes(counter);
+        let prefix = &nonce[..PREFIX_SIZE];
+        if self.peer_prefix.is_some_and(|peer| peer != prefix) {
+            return Err(ParseError::Forged);
+        }
+        if !self.replay.is_fresh(counter) {
+            return Err(ParseError::Replayed);
+        }
+        let mut opened = Vec::with_capacity(Header::SIZE + body.len());
+        opened.extend_from_slice(header);
+        opened.extend_from_slice(body);
+        self.cipher
+            .decrypt_in_place_detached(XNonce::from_slice(nonce), header, &mut opened[Header::SIZE..], Tag::from_slice(tag))
+            .map_err(|_| ParseError::Forged)?;
+        if self.peer_prefix.is_none() {
+            let mut peer = [0u8; PREFIX_SIZE];
+            peer.copy_from_slice(prefix);
+            self.peer_prefix = Some(peer);
+        }
+        self.replay.mark(counter);
+        Ok(opened)
+    }
+}
@@ -0,0 +1,284 @@
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::Shutdown;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use rand::{RngCore, SeedableRng};
+use rand::rngs::StdRng;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use super::connection::{Config, ConnectionStats};
+use super::link::{LinkConfig, LinkStats};
+use super::record::{Recording, Session};
+use super::{GbnStream, Interface};
+
+/// 一次传输的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferReport {
+    pub bytes: u64,
+    pub elapsed_secs: f64,
+    /// 有效吞吐量,单位字节每秒
+    pub goodput: f64,
+    /// 重传的包占发送方发出的所有包的比例
+    pub retransmission_ratio: f64,
+    /// 发送方首次发送的包占所有发出的包的比例
+    pub efficiency: f64,
+    pub sent_sha256: String,
+    pub received_sha256: String,
+    pub verified: bool,
+    pub sender: ConnectionStats,
+    pub receiver: ConnectionStats,
+    /// 经过的每条链路的统计
+    pub links: Vec<LinkStats>,
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 在一个新的`Interface`上把`payload`从左端传到右端,校验收到的数据并汇总统计
+pub fn run_transfer(payload: &[u8], link: LinkConfig, config: Config) -> Result<TransferReport> {
+    run_on_interface(payload, link, config, false, None).map(|(report, _)| report)
+}
+
+/// 与`run_transfer`相同,但超过`limit`还没有传完时关闭两端,返回`TimedOut`
+pub fn run_transfer_within(payload: &[u8], link: LinkConfig, config: Config, limit: Duration) -> Result<TransferReport> {
+    run_on_interface(payload, link, config, false, Some(limit)).map(|(report, _)| report)
+}
+
+/// 与`run_transfer`相同,同时录下两端连接的输入和输出
+pub fn record_transfer(payload: &[u8], link: LinkConfig, config: Config) -> Result<(TransferReport, Session)> {
+    let (report, connections) = run_on_interface(payload, link, config.clone(), true, None)?;
+    Ok((report, Session { config, connections }))
+}
+
+fn run_on_interface(payload: &[u8], link: LinkConfig, config: Config, record: bool, limit: Option<Duration>)
+                    -> Result<(TransferReport, Vec<Recording>)> {
+    let mut interface = Interface::new(link, config);
+    interface.set_linger(Some(Duration::from_secs(1)));
+    interface.set_recording(record);
+    let (sender, receiver) = interface.pair();
+    let ends = [sender.try_clone()?, receiver.try_clone()?];
+    // 到期时关闭两端,阻塞的读写随之返回;传输先结束时丢掉`done`让它提前退出
+    let (done, finished) = channel::<()>();
+    let watchdog = match limit {
+        Some(limit) => {
+            let ends = [sender.try_clone()?, receiver.try_clone()?];
+            Some(thread::spawn(move || {
+                let expired = finished.recv_timeout(limit) == Err(RecvTimeoutError::Timeout);
+                if expired {
+                    for end in &ends {
+                        let _ = end.shutdown(Shutdown::Both);
+                    }
+                }
+                expired
+            }))
+        }
+        None => None,
+    };
+    let result = transfer(payload, sender, receiver);
+    drop(done);
+    if watchdog.is_some_and(|watchdog| watchdog.join().expect("watchdog thread panicked")) {
+        // 没有传完的数据不会再被确认
+        interface.set_linger(None);
+        return Err(Error::new(ErrorKind::TimedOut, format!("transfer did not finish within {:?}", limit.unwrap())));
+    }
+    let mut report = result?;
+    report.links = vec![interface.link_stats()];
+    Ok((report, ends.iter().filter_map(GbnStream::take_recording).collect()))
+}
+
+/// 把`payload`从`sender`传到`receiver`,报告中不含链路统计
+pub fn transfer(payload: &[u8], mut sender: GbnStream, mut receiver: GbnStream) -> Result<TransferReport> {
+    let start = Instant::now();
+    let writer = {
+        let payload = payload.to_vec();
+        thread::spawn(move || -> Result<_> {
+            sender.write_all(&payload)?;
+            sender.flush()?;
+            Ok(sender)
+        })
+    };
+    let mut received = Vec::with_capacity(payload.len());
+    let mut buf = [0u8; 8192];
+    while received.len() < payload.len() {
+        let n = receiver.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        received.extend_from_slice(&buf[..n]);
+    }
+    let elapsed = start.elapsed();
+    let sender = writer.join().expect("writer thread panicked")?;
+    let sender_stats = sender.stats();
+    let elapsed_secs = elapsed.as_secs_f64();
+    let total = sender_stats.packets_sent + sender_stats.packets_retransmitted;
+    let sent_sha256 = sha256_hex(payload);
+    let received_sha256 = sha256_hex(&received);
+    Ok(TransferReport {
+        bytes: received.len() as u64,
+        elapsed_secs,
+        goodput: if elapsed_secs > 0.0 { received.len() as f64 / elapsed_secs } else { 0.0 },
+        retransmission_ratio: if total > 0 { sender_stats.packets_retransmitted as f64 / total as f64 } else { 0.0 },
+        efficiency: sender_stats.efficiency(),
+        verified: sent_sha256 == received_sha256,
+        sent_sha256,
+        received_sha256,
+        sender: sender_stats,
+        receiver: receiver.stats(),
+        links: Vec::new(),
+    })
+}
+
+/// 参数扫描:对损伤和连接参数的每一种组合重复传输若干次
+#[derive(Debug, Clone)]
+pub struct Sweep {
+    pub losses: Vec<f64>,
+    pub windows: Vec<u32>,
+    pub body_sizes: Vec<u32>,
+    pub timeouts: Vec<Duration>,
+    /// 接收方的重排缓冲区大小,0表示不暂存
+    pub reorder_buffers: Vec<u32>,
+    /// 每种组合重复的次数,第`i`次运行使用种子`seed + i`
+    pub runs: u32,
+    pub seed: u64,
+    /// 其余的链路参数,`loss`和`seed`会被覆盖
+    pub link: LinkConfig,
+    /// 每次传输的数据量
+    pub payload_size: usize,
+    /// 每次运行的时限,超过时记为失败,继续下一次运行。None表示不限
+    pub run_timeout: Option<Duration>,
+}
+
+/// 一种参数组合多次运行的汇总
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepRow {
+    pub loss: f64,
+    pub window: u32,
+    pub body_size: u32,
+    pub timeout_ms: u128,
+    pub reorder_buffer: u32,
+    pub runs: u32,
+    /// 收到的数据与发出的不一致、超时或者出错的次数。超时和出错的运行不计入其余的统计
+    pub failures: u32,
+    pub goodput_mean: f64,
+    pub goodput_stddev: f64,
+    pub retransmission_ratio_mean: f64,
+    pub retransmission_ratio_stddev: f64,
+    /// 接收方每次运行平均收到的重复包
+    pub duplicates_mean: f64,
+}
+
+impl SweepRow {
+    pub const CSV_HEADER: &'static str = "loss,window,body_size,timeout_ms,reorder_buffer,runs,failures,\
+goodput_mean,goodput_stddev,retransmission_ratio_mean,retransmission_ratio_stddev,duplicates_mean";
+
+    pub fn write_csv<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w, "{},{},{},{},{},{},{},{:.3},{:.3},{:.6},{:.6},{:.1}",
+                 self.loss, self.window, self.body_size, self.timeout_ms, self.reorder_buffer, self.runs, self.failures,
+                 self.goodput_mean, self.goodput_stddev,
+                 self.retransmission_ratio_mean, self.retransmission_ratio_stddev, self.duplicates_mean)
+    }
+}
+
+/// 样本均值和样本标准差
+fn mean_stddev(samples: &[f64]) -> (f64, f64) {
+    let n = samples.len() as f64;
+    if samples.is_empty() {
+        return (0.0, 0.0);
+    }
+    let mean = samples.iter().sum::<f64>() / n;
+    if samples.len() < 2 {
+        return (mean, 0.0);
+    }
+    let var = samples.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / (n - 1.0);
+    (mean, var.sqrt())
+}
+
+impl Sweep {
+    /// 检查每个取值,和单次传输使用同样的检查,不满足时返回`InvalidInput`
+    pub fn validate(&self) -> Result<()> {
+        for &loss in &self.losses {
+            LinkConfig { loss, ..self.link.clone() }.validate()?;
+        }
+        for &window in &self.windows {
+            Config { window, ..Config::default() }.validate()?;
+        }
+        for &max_body_size in &self.body_sizes {
+            Config { max_body_size, ..Config::default() }.validate()?;
+        }
+        Ok(())
+    }
+
+    /// 依次运行每种组合,每得到一行就交给`on_row`
+    pub fn run<F: FnMut(&SweepRow) -> Result<()>>(&self, mut on_row: F) -> Result<()> {
+        self.validate()?;
+        for &loss in &self.losses {
+            for &window in &self.windows {
+                for &body_size in &self.body_sizes {
+                    for &timeout in &self.timeouts {
+                        for &reorder_buffer in &self.reorder_buffers {
+                            let row = self.run_one(loss, window, body_size, timeout, reorder_buffer)?;
+                            on_row(&row)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn run_one(&self, loss: f64, window: u32, body_size: u32, timeout: Duration, reorder_buffer: u32) -> Result<SweepRow> {
+        let mut goodputs = Vec::with_capacity(self.runs as usize);
+        let mut ratios = Vec::with_capacity(self.runs as usize);
+        let mut duplicates = Vec::with_capacity(self.runs as usize);
+        let mut failures = 0;
+        for i in 0..self.runs {
+            let seed = self.seed.wrapping_add(i as u64);
+            let mut payload = vec![0u8; self.payload_size];
+            StdRng::seed_from_u64(seed).fill_bytes(&mut payload);
+            let link = LinkConfig { loss, seed: Some(seed), ..self.link.clone() };
+            let config = Config { window, max_body_size: body_size, timeout, reorder_buffer, ..Config::default() };
+            let result = match self.run_timeout {
+                Some(limit) => run_transfer_within(&payload, link, config, limit),
+                None => run_transfer(&payload, link, config),
+            };
+            let report = match result {
+                Ok(report) => report,
+                Err(e) => {
+                    warn!("loss={} N={} body={} rto={:?} reorder={} seed={}: {}",
+                          loss, window, body_size, timeout, reorder_buffer, seed, e);
+                    failures += 1;
+                    continue;
+                }
+            };
+            info!("loss={} N={} body={} rto={:?} reorder={} seed={}: {:.0} B/s, {:.3} retransmitted",
+                  loss, window, body_size, timeout, reorder_buffer, seed, report.goodput, report.retransmission_ratio);
+            if !report.verified {
+                failures += 1;
+            }
+            goodputs.push(report.goodput);
+            ratios.push(report.retransmission_ratio);
+            duplicates.push(report.receiver.duplicates_received as f64);
+        }
+        let (goodput_mean, goodput_stddev) = mean_stddev(&goodputs);
+        let (retransmission_ratio_mean, retransmission_ratio_stddev) = mean_stddev(&ratios);
+        Ok(SweepRow {
+            loss,
+            window,
+            body_size,
+            timeout_ms: timeout.as_millis(),
+            reorder_buffer,
+            runs: self.runs,
+            failures,
+            goodput_mean,
+            goodput_stddev,
+            retransmission_ratio_mean,
+            retransmission_ratio_stddev,
+            duplicates_mean: mean_stddev(&duplicates).0,
+        })
+    }
+}
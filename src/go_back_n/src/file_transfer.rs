@@ -0,0 +1,299 @@
+//! `GbnStream`(或者任何可靠的字节流)上的文件传输协议
+//!
+//! 接收方发出请求(文件名,已校验的偏移,这部分内容的SHA-256),发送方回复元数据
+//! (大小,整个文件的SHA-256,开始发送的偏移),然后逐块发送,每块带有自己的SHA-256,
+//! 最后接收方校验整个文件并回复确认。
+//! 接收方只把校验过的块追加到`<目标>.part`,传输中断后再次`fetch`会从它的末尾继续。
+//! 如果它比源文件长,或者内容和源文件的开头不符(源文件在两次传输之间变了),发送方从头发送。
+use std::fs::{self, File, OpenOptions};
+use std::cmp::min;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use log::{debug, info};
+use sha2::{Digest, Sha256};
+
+/// 每块携带的文件内容
+pub const CHUNK_SIZE: usize = 16 * 1024;
+const MAX_NAME_LEN: usize = 4096;
+// 块的头部是偏移和哈希
+const MAX_FRAME_LEN: usize = 8 + 32 + CHUNK_SIZE;
+
+const REQUEST: u8 = 1;
+const METADATA: u8 = 2;
+const CHUNK: u8 = 3;
+const DONE: u8 = 4;
+const CONFIRM: u8 = 5;
+const ERROR: u8 = 6;
+
+type Hash = [u8; 32];
+
+#[derive(Debug)]
+enum Frame {
+    /// `sha256`是接收方已有的前`offset`字节的哈希
+    Request { name: String, offset: u64, sha256: Hash },
+    /// 发送方从`offset`开始发送,接收方已有的部分不能用时为0
+    Metadata { size: u64, sha256: Hash, offset: u64 },
+    Chunk { offset: u64, sha256: Hash, data: Vec<u8> },
+    Done,
+    Confirm(bool),
+    Error(String),
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> Error {
+    Error::new(ErrorKind::InvalidData, e)
+}
+
+fn hash(data: &[u8]) -> Hash {
+    Sha256::digest(data).into()
+}
+
+impl Frame {
+    /// 帧的格式是一字节类型、四字节长度和内容
+    fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        let mut body = Vec::new();
+        let kind = match self {
+            Frame::Request { name, offset, sha256 } => {
+                body.write_u64::<NetworkEndian>(*offset)?;
+                body.extend_from_slice(sha256);
+                body.extend_from_slice(name.as_bytes());
+                REQUEST
+            }
+            Frame::Metadata { size, sha256, offset } => {
+                body.write_u64::<NetworkEndian>(*size)?;
+                body.extend_from_slice(sha256);
+                body.write_u64::<NetworkEndian>(*offset)?;
+                METADATA
+            }
+            Frame::Chunk { offset, sha256, data } => {
+                body.write_u64::<NetworkEndian>(*offset)?;
+                body.extend_from_slice(sha256);
+                body.extend_from_slice(data);
+                CHUNK
+            }
+            Frame::Done => DONE,
+            Frame::Confirm(ok) => {
+                body.push(*ok as u8);
+                CONFIRM
+            }
+            Frame::Error(message) => {
+                body.extend_from_slice(message.as_bytes());
+                ERROR
+            }
+        };
+        w.write_u8(kind)?;
+        w.write_u32::<NetworkEndian>(body.len() as u32)?;
+        w.write_all(&body)?;
+        w.flush()
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let kind = r.read_u8()?;
+        let len = r.read_u32::<NetworkEndian>()? as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(invalid_data(format!("frame of {} bytes is too long", len)));
+        }
+        let mut body = vec![0u8; len];
+        r.read_exact(&mut body)?;
+        let mut body = &body[..];
+        let read_hash = |body: &mut &[u8]| -> Result<Hash> {
+            let mut sha256 = Hash::default();
+            body.read_exact(&mut sha256)?;
+            Ok(sha256)
+        };
+        let utf8 = |body: &[u8]| String::from_utf8(body.to_vec()).map_err(invalid_data);
+        let frame = match kind {
+            REQUEST => {
+                let offset = body.read_u64::<NetworkEndian>()?;
+                let sha256 = read_hash(&mut body)?;
+                if body.len() > MAX_NAME_LEN {
+                    return Err(invalid_data("file name is too long"));
+                }
+                Frame::Request { name: utf8(body)?, offset, sha256 }
+            }
+            METADATA => {
+                let size = body.read_u64::<NetworkEndian>()?;
+                let sha256 = read_hash(&mut body)?;
+                Frame::Metadata { size, sha256, offset: body.read_u64::<NetworkEndian>()? }
+            }
+            CHUNK => {
+                let offset = body.read_u64::<NetworkEndian>()?;
+                let sha256 = read_hash(&mut body)?;
+                Frame::Chunk { offset, sha256, data: body.to_vec() }
+            }
+            DONE => Frame::Done,
+            CONFIRM => Frame::Confirm(body.read_u8()? != 0),
+            ERROR => Frame::Error(utf8(body)?),
+            _ => return Err(invalid_data(format!("unknown frame type {}", kind))),
+        };
+        Ok(frame)
+    }
+}
+
+/// 只允许请求`root`之下的普通路径
+fn resolve(root: &Path, name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if name.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    Some(root.join(path))
+}
+
+/// 从头读完`file`,返回累积了它的内容的哈希状态
+fn hash_file(file: &mut File) -> Result<Sha256> {
+    Ok(hash_prefix(file, u64::MAX)?.1)
+}
+
+/// 从头读完`file`,返回它前`len`字节的哈希(文件不够长时为None)和累积了全部内容的哈希状态
+fn hash_prefix(file: &mut File, len: u64) -> Result<(Option<Hash>, Sha256)> {
+    let mut hasher = Sha256::new();
+    let mut prefix = None;
+    let mut read = 0;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    file.seek(SeekFrom::Start(0))?;
+    loop {
+        if read == len {
+            prefix = Some(hasher.clone().finalize().into());
+        }
+        // 在`len`处停一下,好取前缀的哈希
+        let want = if read < len { min(len - read, CHUNK_SIZE as u64) as usize } else { CHUNK_SIZE };
+        let n = file.read(&mut buf[..want])?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        read += n as u64;
+    }
+    Ok((prefix, hasher))
+}
+
+/// 响应一个请求,把`root`下的文件发给对方,返回发送的字节数
+pub fn serve<S: Read + Write>(stream: &mut S, root: &Path) -> Result<u64> {
+    let (name, requested, prefix) = match Frame::read_from(stream)? {
+        Frame::Request { name, offset, sha256 } => (name, offset, sha256),
+        frame => return Err(invalid_data(format!("expected a request, got {:?}", frame))),
+    };
+    let opened = resolve(root, &name)
+        .ok_or_else(|| Error::new(ErrorKind::PermissionDenied, "invalid file name"))
+        .and_then(File::open);
+    let mut file = match opened {
+        Ok(file) => file,
+        Err(e) => {
+            Frame::Error(format!("{}: {}", name, e)).write_to(stream)?;
+            return Err(e);
+        }
+    };
+    let size = file.metadata()?.len();
+    let (matched, hasher) = hash_prefix(&mut file, requested)?;
+    let sha256 = hasher.finalize().into();
+    // 对方已有的部分比文件长或者内容不符时从头发送
+    let offset = if matched == Some(prefix) { requested } else { 0 };
+    if offset != requested {
+        info!("Partial {} ({} bytes) does not match, sending from the start", name, requested);
+    }
+    Frame::Metadata { size, sha256, offset }.write_to(stream)?;
+    info!("Serving {} ({} bytes) from offset {}", name, size, offset);
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut sent = 0;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let data = buf[..n].to_vec();
+        Frame::Chunk { offset: offset + sent, sha256: hash(&data), data }.write_to(stream)?;
+        sent += n as u64;
+    }
+    Frame::Done.write_to(stream)?;
+    match Frame::read_from(stream)? {
+        Frame::Confirm(true) => Ok(sent),
+        Frame::Confirm(false) => Err(invalid_data("peer rejected the file hash")),
+        frame => Err(invalid_data(format!("expected a confirmation, got {:?}", frame))),
+    }
+}
+
+/// `fetch`的结果
+#[derive(Debug, Clone)]
+pub struct FetchReport {
+    pub size: u64,
+    /// 上一次中断时已经校验过的字节数
+    pub resumed_from: u64,
+    pub sha256: Hash,
+}
+
+/// `dest`对应的未完成文件
+pub fn partial_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    dest.with_file_name(name)
+}
+
+/// 向对方请求文件`name`并保存到`dest`。如果之前的传输中断过,从已校验的部分之后继续
+pub fn fetch<S: Read + Write>(stream: &mut S, name: &str, dest: &Path) -> Result<FetchReport> {
+    let part = partial_path(dest);
+    let mut file = OpenOptions::new().read(true).append(true).create(true).open(&part)?;
+    let mut resumed_from = file.metadata()?.len();
+    // 已经校验过的部分也要算进整个文件的哈希
+    let mut hasher = hash_file(&mut file)?;
+
+    let prefix = hasher.clone().finalize().into();
+    Frame::Request { name: name.to_string(), offset: resumed_from, sha256: prefix }.write_to(stream)?;
+    let (size, sha256, offset) = match Frame::read_from(stream)? {
+        Frame::Metadata { size, sha256, offset } => (size, sha256, offset),
+        Frame::Error(message) => {
+            // 不留下刚刚创建的空文件
+            if resumed_from == 0 {
+                drop(file);
+                fs::remove_file(&part)?;
+            }
+            return Err(Error::other(message));
+        }
+        frame => return Err(invalid_data(format!("expected metadata, got {:?}", frame))),
+    };
+    if offset != resumed_from {
+        if offset != 0 {
+            return Err(invalid_data(format!("unexpected starting offset {}", offset)));
+        }
+        // 已有的部分和源文件不符,丢掉重新开始
+        debug!("Discarding {} bytes of {}", resumed_from, part.display());
+        file.set_len(0)?;
+        hasher = Sha256::new();
+        resumed_from = 0;
+    }
+    debug!("Fetching {} ({} bytes) from offset {}", name, size, resumed_from);
+
+    let mut offset = resumed_from;
+    loop {
+        match Frame::read_from(stream)? {
+            Frame::Chunk { offset: at, sha256, data } => {
+                if at != offset || offset + data.len() as u64 > size {
+                    return Err(invalid_data(format!("unexpected chunk at {}", at)));
+                }
+                if hash(&data) != sha256 {
+                    return Err(invalid_data(format!("chunk at {} failed verification", at)));
+                }
+                file.write_all(&data)?;
+                hasher.update(&data);
+                offset += data.len() as u64;
+            }
+            Frame::Done => break,
+            Frame::Error(message) => return Err(Error::other(message)),
+            frame => return Err(invalid_data(format!("expected a chunk, got {:?}", frame))),
+        }
+    }
+    let ok = offset == size && <Hash>::from(hasher.finalize()) == sha256;
+    Frame::Confirm(ok).write_to(stream)?;
+    if !ok {
+        // 源文件在两次传输之间变了,已有的部分不能再用
+        drop(file);
+        fs::remove_file(&part)?;
+        return Err(invalid_data(format!("{} failed verification", name)));
+    }
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&part, dest)?;
+    Ok(FetchReport { size, resumed_from, sha256 })
+}
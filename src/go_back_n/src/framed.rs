@@ -0,0 +1,129 @@
+//! 字节流上的消息分帧:每帧前面是LEB128变长编码的长度
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::GbnStream;
+
+/// u64的LEB128编码最长10字节
+const MAX_VARINT_LEN: usize = 10;
+
+/// 在`GbnStream`或者任何字节流上收发完整的消息。
+/// 阻塞的流用`send_frame`/`recv_frame`,异步的流用`send_frame_async`/`recv_frame_async`
+pub struct FramedGbn<S = GbnStream> {
+    inner: S,
+    max_frame_size: usize,
+}
+
+fn encode_varint(mut value: u64, buf: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// 逐字节解码变长整数,`next`每次返回下一个字节
+fn decode_varint<F: FnMut() -> Result<u8>>(mut next: F) -> Result<u64> {
+    let mut value = 0u64;
+    for i in 0..MAX_VARINT_LEN {
+        let byte = next()?;
+        let bits = (byte & 0x7f) as u64;
+        if i == MAX_VARINT_LEN - 1 && bits > 1 {
+            break;
+        }
+        value |= bits << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::new(ErrorKind::InvalidData, "frame length overflows u64"))
+}
+
+impl<S> FramedGbn<S> {
+    /// 默认的最大帧长度
+    pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+    pub fn new(inner: S) -> Self {
+        Self::with_max_frame_size(inner, Self::MAX_FRAME_SIZE)
+    }
+    /// 超过`max_frame_size`的帧发送时返回`InvalidInput`,接收时返回`InvalidData`
+    pub fn with_max_frame_size(inner: S, max_frame_size: usize) -> Self {
+        Self { inner, max_frame_size }
+    }
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// 长度前缀加上帧内容
+    fn encode(&self, frame: &[u8]) -> Result<Vec<u8>> {
+        if frame.len() > self.max_frame_size {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  format!("frame of {} bytes exceeds the limit of {}", frame.len(), self.max_frame_size)));
+        }
+        let mut buf = Vec::with_capacity(MAX_VARINT_LEN + frame.len());
+        encode_varint(frame.len() as u64, &mut buf);
+        buf.extend_from_slice(frame);
+        Ok(buf)
+    }
+    fn check_len(&self, len: u64) -> Result<usize> {
+        if len > self.max_frame_size as u64 {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("frame of {} bytes exceeds the limit of {}", len, self.max_frame_size)));
+        }
+        Ok(len as usize)
+    }
+}
+
+impl<S: Read + Write> FramedGbn<S> {
+    pub fn send_frame(&mut self, frame: &[u8]) -> Result<()> {
+        let buf = self.encode(frame)?;
+        self.inner.write_all(&buf)?;
+        self.inner.flush()
+    }
+    /// 在帧的中间遇到EOF时返回`UnexpectedEof`
+    pub fn recv_frame(&mut self) -> Result<Vec<u8>> {
+        let inner = &mut self.inner;
+        let len = decode_varint(|| {
+            let mut byte = [0u8];
+            inner.read_exact(&mut byte)?;
+            Ok(byte[0])
+        })?;
+        let mut frame = vec![0u8; self.check_len(len)?];
+        self.inner.read_exact(&mut frame)?;
+        Ok(frame)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> FramedGbn<S> {
+    pub async fn send_frame_async(&mut self, frame: &[u8]) -> Result<()> {
+        let buf = self.encode(frame)?;
+        self.inner.write_all(&buf).await?;
+        self.inner.flush().await
+    }
+    pub async fn recv_frame_async(&mut self) -> Result<Vec<u8>> {
+        let mut prefix = Vec::with_capacity(MAX_VARINT_LEN);
+        loop {
+            let mut byte = [0u8];
+            self.inner.read_exact(&mut byte).await?;
+            prefix.push(byte[0]);
+            if byte[0] & 0x80 == 0 || prefix.len() == MAX_VARINT_LEN {
+                break;
+            }
+        }
+        let mut bytes = prefix.into_iter();
+        let len = decode_varint(|| bytes.next().ok_or_else(|| Error::new(ErrorKind::InvalidData, "frame length overflows u64")))?;
+        let mut frame = vec![0u8; self.check_len(len)?];
+        self.inner.read_exact(&mut frame).await?;
+        Ok(frame)
+    }
+}
@@ -1,150 +1,513 @@
-use std::io::{Read, Result, Write};
-use std::sync::{Arc, Condvar, Mutex};
-use std::sync::mpsc::{channel, Receiver};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::Shutdown;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::task::{Context, Poll, Waker};
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use log::trace;
+use log::{error, trace};
 use rand::random;
+use serde::Serialize;
 
-use connection::{Connection, PacketWrapper};
-use packet::Packet;
+use connection::{Config, Connection, ConnectionSnapshot, ConnectionStats, PacketWrapper};
+use link::{Link, LinkConfig, LinkStats, Medium};
+use packet::Summary;
+use record::Recording;
+use timer::TimerHeap;
 
 pub mod packet;
 pub mod connection;
+pub mod link;
+pub mod experiment;
+pub mod file_transfer;
+pub mod udp;
+pub mod tunnel;
+pub mod topology;
+pub mod mux;
+pub mod split;
+pub mod framed;
+pub mod crypto;
+pub mod record;
+pub mod async_io;
+pub mod cli;
+mod timer;
 
 type InterfaceHandle = Arc<FooBar>;
 
-fn packet_loop(ih: InterfaceHandle) {
+// 进程中所有的连接,包括UDP上的,`snapshot_all`从这里取快照
+static ENDPOINTS: Mutex<Vec<Weak<Endpoint>>> = Mutex::new(Vec::new());
+
+/// 进程中所有还存在的连接的快照
+pub fn snapshot_all() -> Vec<ConnectionSnapshot> {
+    let endpoints: Vec<Arc<Endpoint>> = ENDPOINTS.lock().unwrap().iter().filter_map(Weak::upgrade).collect();
+    let now = Instant::now();
+    endpoints.iter().map(|endpoint| endpoint.lock().snapshot_at(now)).collect()
+}
+
+/// 把所有还存在的连接的快照写到`w`,每行一个JSON对象
+pub fn dump<W: Write>(mut w: W) -> Result<()> {
+    for snapshot in snapshot_all() {
+        serde_json::to_writer(&mut w, &snapshot)?;
+        w.write_all(b"\n")?;
+    }
+    w.flush()
+}
+
+/// 每次收到SIGUSR1时把所有连接的快照输出到标准错误
+#[cfg(unix)]
+pub fn dump_on_signal() -> Result<()> {
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGUSR1])?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if let Err(e) = dump(std::io::stderr().lock()) {
+                error!("failed to dump connections: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// `packet_loop`异常退出时,让所有连接以`ConnectionReset`失效,唤醒阻塞的读写者
+struct LoopGuard(InterfaceHandle);
+
+impl Drop for LoopGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            for endpoint in self.0.endpoints.read().unwrap().iter() {
+                endpoint.fail(ErrorKind::ConnectionReset);
+            }
+        }
+    }
+}
+
+/// 睡眠到最早的定时器到期、链路上有包到达或者有新的包发出为止
+fn packet_loop(ih: InterfaceHandle, rx: Receiver<PacketWrapper>) {
+    let _guard = LoopGuard(ih.clone());
+    let mut timers = TimerHeap::default();
     loop {
-        if let Ok(packet) = ih.rx.lock().unwrap().recv_timeout(Duration::from_millis(10)) {
-            let is_left_side = packet.is_left_side();
-            let packet = packet.unwrap();
-            if random::<u8>() > 200 {
-                trace!("Loop: Ignored {} from Connection[{}]", Packet::parse(packet.as_ref()).unwrap().header, is_left_side as usize);
-                continue;
+        let now = Instant::now();
+        while let Some(id) = timers.pop_expired(now) {
+            if let Some(deadline) = ih.get_endpoint(id).on_tick(now) {
+                timers.schedule(deadline, id);
+            }
+        }
+        loop {
+            let delivered = ih.medium.lock().unwrap().poll_delivery(now);
+            match delivered {
+                Some((to, packet)) => if let Some(deadline) = ih.get_endpoint(to).on_packet(&packet, now) {
+                    timers.schedule(deadline, to);
+                },
+                None => break,
+            }
+        }
+        let deadline = timers.next_deadline().into_iter().chain(ih.medium.lock().unwrap().next_event()).min();
+        let packet = match deadline {
+            Some(deadline) => match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(packet) => packet,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(packet) => packet,
+                Err(_) => break,
             }
-            let mut c = ih.get_connection(!is_left_side).lock().unwrap();
-            c.on_packet(packet);
-            if !c.incoming.is_empty() {
-                ih.rcv_var.notify_all();
+        };
+        let id = packet.id;
+        // 发送方在发包的同时可能启动了重传定时器。已经排上的定时器只会比它更早,
+        // 所以只有在没有排上时才需要去锁发送方
+        if !timers.is_scheduled(id) {
+            if let Some(deadline) = ih.get_endpoint(id).connection.lock().unwrap().next_deadline() {
+                timers.schedule(deadline, id);
             }
-        } else {
-            ih.left.lock().unwrap().on_tick().unwrap();
-            ih.right.lock().unwrap().on_tick().unwrap();
+        }
+        let packet = packet.unwrap();
+        if !ih.medium.lock().unwrap().send_from(Instant::now(), id, packet.clone()) {
+            trace!("Loop: Ignored {} from Connection[{}]", Summary(&packet), id);
         }
     }
 }
 
-struct FooBar {
-    left: Mutex<Connection>,
-    right: Mutex<Connection>,
-    rcv_var: Condvar,
-    rx: Mutex<Receiver<PacketWrapper>>,
+/// 一个连接以及它自己的读写通知
+struct Endpoint {
+    connection: Mutex<Connection>,
+    // 有新数据可读或者连接已关闭
+    readable: Condvar,
+    // 发送窗口向前滑动,发送缓冲区有了空间
+    writable: Condvar,
+    // 等待同样事件的`AsyncGbnStream`
+    read_wakers: Mutex<Vec<Waker>>,
+    write_wakers: Mutex<Vec<Waker>>,
 }
 
-impl Default for FooBar {
-    fn default() -> Self {
-        let (tx, rx) = channel();
-        let left = Mutex::new(Connection::new(true, tx.clone()));
-        let right = Mutex::new(Connection::new(false, tx));
-        Self {
-            left,
-            right,
-            rcv_var: Condvar::new(),
-            rx: Mutex::new(rx),
+impl Endpoint {
+    fn new(connection: Connection) -> Arc<Self> {
+        let endpoint = Arc::new(Self {
+            connection: Mutex::new(connection),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+            read_wakers: Mutex::new(Vec::new()),
+            write_wakers: Mutex::new(Vec::new()),
+        });
+        let mut endpoints = ENDPOINTS.lock().unwrap();
+        endpoints.retain(|endpoint| endpoint.strong_count() > 0);
+        endpoints.push(Arc::downgrade(&endpoint));
+        endpoint
+    }
+    /// 处理定时器到期,返回下一个定时器的期限
+    fn on_tick(&self, now: Instant) -> Option<Instant> {
+        let mut c = self.connection.lock().unwrap();
+        if let Err(e) = c.on_tick_at(now) {
+            trace!("Loop: Connection[{}] failed: {}", c.id(), e);
+            self.wake_readers();
+            self.wake_writers();
+        }
+        c.next_deadline()
+    }
+    /// 把送达的包交给连接并唤醒等待它的读写者,返回下一个定时器的期限
+    fn on_packet(&self, packet: &[u8], now: Instant) -> Option<Instant> {
+        let mut c = self.connection.lock().unwrap();
+        let (base, generation) = (c.send_space().base, c.credit_generation());
+        if let Err(e) = c.on_packet_at(packet, now) {
+            trace!("Loop: Dropped packet to Connection[{}]: {}", c.id(), e);
+        }
+        if c.has_readable() || c.is_closed() {
+            self.wake_readers();
+        }
+        if c.send_space().base != base || c.credit_generation() != generation || c.is_closed() {
+            self.wake_writers();
+        }
+        c.next_deadline()
+    }
+    /// 数据都已被确认,或者已经等到了`deadline`
+    fn linger(&self, deadline: Instant) {
+        let mut c = self.lock();
+        while !c.is_idle() {
+            let now = Instant::now();
+            if now >= deadline {
+                return;
+            }
+            c = self.writable.wait_timeout(c, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+    fn close(&self) {
+        self.lock().close();
+        self.wake_readers();
+        self.wake_writers();
+    }
+    /// 锁可能因为`packet_loop`恐慌而中毒,这时连接会以`ConnectionReset`失效,
+    /// 所以仍然把它交出去,由`check`报告错误
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    fn fail(&self, kind: ErrorKind) {
+        let mut c = self.lock();
+        if !c.is_closed() {
+            c.fail(kind);
+        }
+        drop(c);
+        self.wake_readers();
+        self.wake_writers();
+    }
+    fn wake_readers(&self) {
+        self.readable.notify_all();
+        self.read_wakers.lock().unwrap().drain(..).for_each(Waker::wake);
+    }
+    fn wake_writers(&self) {
+        self.writable.notify_all();
+        self.write_wakers.lock().unwrap().drain(..).for_each(Waker::wake);
+    }
+    /// 必须在持有连接的锁时登记,这样唤醒不会发生在检查状态和登记之间
+    fn register(wakers: &Mutex<Vec<Waker>>, waker: &Waker) {
+        let mut wakers = wakers.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+    /// 发送缓冲区满时返回None
+    fn try_write(c: &mut Connection, buf: &[u8]) -> Option<Result<usize>> {
+        if let Err(e) = c.check() {
+            return Some(Err(e));
+        }
+        if c.is_closed() {
+            return Some(Err(Error::new(ErrorKind::BrokenPipe, "interface shut down")));
+        }
+        if c.is_write_closed(0) {
+            return Some(Err(Error::new(ErrorKind::BrokenPipe, "stream shut down for writing")));
+        }
+        if !buf.is_empty() && c.send_buffer_space() == 0 {
+            return None;
+        }
+        let n = c.enqueue(buf);
+        Some(c.send_if_could().map(|_| n))
+    }
+    /// 在默认流上写入,发送缓冲区满时阻塞
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let mut c = self.lock();
+        loop {
+            if let Some(result) = Self::try_write(&mut c, buf) {
+                return result;
+            }
+            c = self.writable.wait(c).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+    fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let mut c = self.lock();
+        match Self::try_write(&mut c, buf) {
+            Some(result) => Poll::Ready(result),
+            None => {
+                Self::register(&self.write_wakers, cx.waker());
+                Poll::Pending
+            }
         }
     }
+    fn flush(&self) -> Result<()> {
+        let mut c = self.lock();
+        c.check()?;
+        if c.is_closed() {
+            return Err(Error::new(ErrorKind::BrokenPipe, "interface shut down"));
+        }
+        c.send_if_could()
+    }
+    /// 没有数据可读时返回None
+    fn try_read(c: &mut Connection, buf: &mut [u8]) -> Option<Result<usize>> {
+        if c.has_incoming() {
+            return Some(Ok(c.read_incoming(buf)));
+        }
+        if c.stream_is_eof(0) {
+            return Some(Ok(0));
+        }
+        if c.is_closed() {
+            return Some(c.check().map(|_| 0));
+        }
+        None
+    }
+    /// 从默认流读取,没有数据时阻塞,对方发来FIN后读到EOF
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut c = self.lock();
+        loop {
+            if let Some(result) = Self::try_read(&mut c, buf) {
+                return result;
+            }
+            c = self.readable.wait(c).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        let mut c = self.lock();
+        match Self::try_read(&mut c, buf) {
+            Some(result) => Poll::Ready(result),
+            None => {
+                Self::register(&self.read_wakers, cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+    fn shutdown(&self, how: Shutdown) -> Result<()> {
+        let mut c = self.lock();
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            c.shutdown_read(0);
+            // 唤醒阻塞在`read`上的线程
+            self.wake_readers();
+        }
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            c.shutdown_write(0);
+            self.wake_writers();
+        }
+        if c.is_closed() {
+            return c.check();
+        }
+        c.send_if_could()
+    }
+}
+
+struct FooBar {
+    // 第`2k`和`2k+1`个连接互为一对
+    endpoints: RwLock<Vec<Arc<Endpoint>>>,
+    // 关闭后置为None,所有发送端都被释放后`packet_loop`会退出
+    tx: Mutex<Option<Sender<PacketWrapper>>>,
+    medium: Mutex<Box<dyn Medium>>,
+    config: Config,
+    // 新建的连接是否录制输入和输出
+    recording: AtomicBool,
 }
 
 impl FooBar {
-    fn get_connection(&self, is_left_side: bool) -> &Mutex<Connection> {
-        if is_left_side {
-            &self.left
-        } else {
-            &self.right
+    fn new(medium: Box<dyn Medium>, config: Config) -> (Self, Receiver<PacketWrapper>) {
+        let (tx, rx) = channel();
+        (Self {
+            endpoints: RwLock::new(Vec::new()),
+            tx: Mutex::new(Some(tx)),
+            medium: Mutex::new(medium),
+            config,
+            recording: AtomicBool::new(false),
+        }, rx)
+    }
+    fn get_endpoint(&self, id: usize) -> Arc<Endpoint> {
+        self.endpoints.read().unwrap()[id].clone()
+    }
+    /// 新建一对连接。`attach`在连接发出第一个包之前拿到左边连接的编号。
+    /// 可能恐慌的构造在加锁之前完成,恐慌不会让锁中毒
+    fn new_pair<F: FnOnce(usize)>(&self, attach: F) -> (Arc<Endpoint>, Arc<Endpoint>) {
+        let tx = self.tx.lock().unwrap().clone();
+        let tx = tx.expect("interface already shut down");
+        let mut left = Connection::with_config(0, tx.clone(), self.config.clone(), random());
+        let mut right = Connection::with_config(0, tx, self.config.clone(), random());
+        if self.recording.load(Ordering::Relaxed) {
+            let epoch = Instant::now();
+            left.start_recording(epoch);
+            right.start_recording(epoch);
+        }
+        let mut endpoints = self.endpoints.write().unwrap();
+        let id = endpoints.len();
+        attach(id);
+        left.set_id(id);
+        right.set_id(id + 1);
+        // `packet_loop`还活着,信道不会断开
+        left.connect().unwrap();
+        right.connect().unwrap();
+        let left = Endpoint::new(left);
+        let right = Endpoint::new(right);
+        endpoints.push(left.clone());
+        endpoints.push(right.clone());
+        (left, right)
+    }
+    fn linger(&self, deadline: Instant) {
+        for endpoint in self.endpoints.read().unwrap().iter() {
+            endpoint.linger(deadline);
+        }
+    }
+    fn snapshot(&self) -> InterfaceSnapshot {
+        let now = Instant::now();
+        let connections = self.endpoints.read().unwrap().iter().map(|endpoint| endpoint.lock().snapshot_at(now)).collect();
+        InterfaceSnapshot { connections, links: self.medium.lock().unwrap().link_stats() }
+    }
+    /// 关闭所有连接。阻塞在`read`上的线程会读到EOF
+    fn shutdown(&self) {
+        drop(self.tx.lock().unwrap().take());
+        for endpoint in self.endpoints.read().unwrap().iter() {
+            endpoint.close();
         }
     }
 }
 
+/// 一个`Interface`上所有连接和链路的状态
+#[derive(Debug, Clone, Serialize)]
+pub struct InterfaceSnapshot {
+    pub connections: Vec<ConnectionSnapshot>,
+    pub links: Vec<LinkStats>,
+}
+
 pub struct Interface {
     ih: Option<InterfaceHandle>,
     jh: Option<JoinHandle<()>>,
+    linger: Option<Duration>,
 }
 
 impl Drop for Interface {
     fn drop(&mut self) {
-        drop(self.ih.take());
-        self.jh
+        let ih = self.ih.take().expect("interface dropped more than once");
+        if let Some(linger) = self.linger {
+            ih.linger(Instant::now() + linger);
+        }
+        ih.shutdown();
+        drop(ih);
+        let jh = self.jh
             .take()
-            .expect("interface dropped more than once")
-            .join()
-            .unwrap();
+            .expect("interface dropped more than once");
+        if jh.join().is_err() {
+            error!("packet loop panicked");
+        }
     }
 }
 
 impl Default for Interface {
     fn default() -> Self {
-        let ih = InterfaceHandle::default();
+        Self::new(LinkConfig::default(), Config::default())
+    }
+}
+
+impl Interface {
+    /// 以给定的链路损伤和连接参数新建,之后`pair`得到的连接都使用这份参数
+    pub fn new(link: LinkConfig, config: Config) -> Self {
+        Self::with_medium(Box::new(Link::new(link)), config)
+    }
+    fn with_medium(medium: Box<dyn Medium>, config: Config) -> Self {
+        let (ih, rx) = FooBar::new(medium, config);
+        let ih = InterfaceHandle::new(ih);
         let jh = {
             let ih = ih.clone();
-            thread::spawn(move || packet_loop(ih))
+            thread::spawn(move || packet_loop(ih, rx))
         };
         Self {
             ih: Some(ih),
             jh: Some(jh),
+            linger: None,
         }
     }
-}
-
-impl Interface {
+    pub fn link_stats(&self) -> LinkStats {
+        self.ih.as_ref().unwrap().medium.lock().unwrap().link_stats().swap_remove(0)
+    }
+    /// 设置`Interface`被丢弃时最多等待多久让已发送的数据被确认,`None`表示立即关闭
+    pub fn set_linger(&mut self, linger: Option<Duration>) {
+        self.linger = linger;
+    }
+    pub fn linger(&self) -> Option<Duration> {
+        self.linger
+    }
+    /// 之后`pair`得到的连接是否录制输入和输出,见`GbnStream::take_recording`
+    pub fn set_recording(&mut self, recording: bool) {
+        self.ih.as_ref().unwrap().recording.store(recording, Ordering::Relaxed);
+    }
+    /// 可以在任何时候调用,包括传输停滞的时候
+    pub fn snapshot(&self) -> InterfaceSnapshot {
+        self.ih.as_ref().unwrap().snapshot()
+    }
+    /// 新建一对相互连接的流,每次调用得到的都是独立的一对
     pub fn pair(&self) -> (GbnStream, GbnStream) {
-        (GbnStream {
-            is_left_side: true,
-            ih: self.ih.clone().unwrap(),
-        }, GbnStream {
-            is_left_side: false,
-            ih: self.ih.clone().unwrap(),
-        })
+        let (left, right) = self.ih.as_ref().unwrap().new_pair(|_| ());
+        (GbnStream { endpoint: left }, GbnStream { endpoint: right })
     }
 }
 
 pub struct GbnStream {
-    ih: InterfaceHandle,
-    is_left_side: bool,
+    endpoint: Arc<Endpoint>,
+}
+
+impl GbnStream {
+    pub fn stats(&self) -> ConnectionStats {
+        self.endpoint.lock().stats().clone()
+    }
+    pub fn snapshot(&self) -> ConnectionSnapshot {
+        self.endpoint.lock().snapshot()
+    }
+    /// 得到同一个连接的另一个句柄,可以在别的线程中同时读写
+    pub fn try_clone(&self) -> Result<GbnStream> {
+        Ok(GbnStream { endpoint: self.endpoint.clone() })
+    }
+    /// 关闭读、写或者两个方向。关闭写之后,已经写入的数据发完时发出FIN,对方随后读到EOF;
+    /// 关闭读之后,`read`立即返回EOF,收到的数据都被丢弃
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        self.endpoint.shutdown(how)
+    }
+    /// 停止录制并取出连接到目前为止的记录,没有在录制时返回None
+    pub fn take_recording(&self) -> Option<Recording> {
+        self.endpoint.lock().take_recording()
+    }
 }
 
 impl Write for GbnStream {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let mut c = self.ih.get_connection(self.is_left_side).lock().unwrap();
-        c.unsent.extend(buf.iter());
-        Ok(buf.len())
+        self.endpoint.write(buf)
     }
     fn flush(&mut self) -> Result<()> {
-        let mut c = self.ih.get_connection(self.is_left_side).lock().unwrap();
-        c.send_if_could();
-        Ok(())
+        self.endpoint.flush()
     }
 }
 
 impl Read for GbnStream {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let mut c = self.ih.get_connection(self.is_left_side).lock().unwrap();
-        loop {
-            if !c.incoming.is_empty() {
-                let mut nread = 0;
-                let (head, tail) = c.incoming.as_slices();
-                let hread = std::cmp::min(buf.len(), head.len());
-                buf[..hread].copy_from_slice(&head[..hread]);
-                nread += hread;
-                let tread = std::cmp::min(buf.len() - nread, tail.len());
-                buf[hread..(hread + tread)].copy_from_slice(&tail[..tread]);
-                nread += tread;
-                drop(c.incoming.drain(..nread));
-                return Ok(nread);
-            }
-            c = self.ih.rcv_var.wait(c).unwrap();
-        }
+        self.endpoint.read(buf)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,344 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde::Serialize;
+
+use super::packet::PacketBuf;
+
+/// 模拟链路的损伤参数
+#[derive(Debug, Clone)]
+pub struct LinkConfig {
+    /// 每个包被丢弃的概率
+    pub loss: f64,
+    /// 单向传播时延
+    pub delay: Duration,
+    /// 随机数种子,None时每次运行的丢包情况都不同
+    pub seed: Option<u64>,
+    /// 瓶颈带宽,单位比特每秒,None时不限速
+    pub bandwidth: Option<u64>,
+    /// 按记录的发送机会限速,设置后忽略`bandwidth`
+    pub trace: Option<Arc<DeliveryTrace>>,
+    /// 瓶颈前的队列,None时队列无限长。需要`bandwidth`或`trace`限速,否则队列不会积累
+    pub queue: Option<QueueConfig>,
+    /// 链路能通过的最大包长,更长的包被丢弃,None时不限制
+    pub mtu: Option<usize>,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            loss: 55.0 / 256.0,
+            delay: Duration::from_millis(0),
+            seed: None,
+            bandwidth: None,
+            trace: None,
+            queue: None,
+            mtu: None,
+        }
+    }
+}
+
+impl LinkConfig {
+    /// 检查参数的取值范围,不满足时返回`InvalidInput`。丢包率为1时传输永远不会结束
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |msg: String| Err(Error::new(ErrorKind::InvalidInput, msg));
+        if !(0.0..1.0).contains(&self.loss) {
+            return invalid(format!("loss must be in [0, 1), got {}", self.loss));
+        }
+        if self.bandwidth == Some(0) {
+            return invalid("bandwidth must be positive".to_string());
+        }
+        if let Some(queue) = &self.queue {
+            if queue.limit == 0 {
+                return invalid("queue limit must be positive".to_string());
+            }
+            // 不限速时包到了就发出去,队列永远是空的
+            if self.bandwidth.is_none() && self.trace.is_none() {
+                return invalid("a queue needs a bandwidth or a trace to drain it".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 队列满时的丢包策略
+#[derive(Debug, Clone, Copy)]
+pub enum QueueDiscipline {
+    /// 队列满了才丢弃新来的包
+    DropTail,
+    /// Random Early Detection:平均队长在两个阈值之间时按线性增长的概率提前丢包,
+    /// 超过`max_threshold`时全部丢弃
+    Red {
+        min_threshold: f64,
+        max_threshold: f64,
+        max_p: f64,
+        /// 平均队长的指数加权系数
+        weight: f64,
+    },
+}
+
+impl QueueDiscipline {
+    /// 以队列长度的1/4和3/4为阈值的RED
+    pub fn red(limit: usize) -> Self {
+        QueueDiscipline::Red {
+            min_threshold: limit as f64 / 4.0,
+            max_threshold: limit as f64 * 3.0 / 4.0,
+            max_p: 0.1,
+            weight: 0.002,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct QueueConfig {
+    /// 队列最多容纳的包数,包括正在发送的那个
+    pub limit: usize,
+    pub discipline: QueueDiscipline,
+}
+
+/// 每毫秒的发送机会,格式与mahimahi相同:每行一个毫秒时间戳,
+/// 每个时间戳可以发出最多`DeliveryTrace::MTU`字节,同一毫秒可以出现多次。记录放完后从头循环
+#[derive(Debug, Clone)]
+pub struct DeliveryTrace {
+    opportunities: Vec<u64>,
+    period: u64,
+}
+
+impl DeliveryTrace {
+    pub const MTU: usize = 1500;
+
+    pub fn parse(s: &str) -> Result<Self> {
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+        let mut opportunities = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let ms: u64 = line.parse().map_err(|e| invalid(format!("line {}: {}", i + 1, e)))?;
+            if opportunities.last().is_some_and(|&last| ms < last) {
+                return Err(invalid(format!("line {}: timestamps must not decrease", i + 1)));
+            }
+            opportunities.push(ms);
+        }
+        let period = match opportunities.last() {
+            Some(&last) if last > 0 => last,
+            _ => return Err(invalid("trace must end after time 0".to_string())),
+        };
+        Ok(Self { opportunities, period })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// 第`k`个发送机会距离起点的时间
+    fn at(&self, k: u64) -> Duration {
+        let n = self.opportunities.len() as u64;
+        Duration::from_millis(k / n * self.period + self.opportunities[(k % n) as usize])
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct LinkStats {
+    /// 交给链路的包
+    pub packets: u64,
+    pub bytes: u64,
+    /// 被链路随机丢弃的包
+    pub dropped: u64,
+    /// 因为队列满了或者RED被丢弃的包
+    pub queue_dropped: u64,
+    /// 出现过的最长队列
+    pub max_queue: usize,
+    /// 超过MTU而被丢弃的包
+    pub oversized: u64,
+}
+
+/// 一个方向上的瓶颈和在途的包
+struct Direction<K> {
+    // 还在队列中的包发送完成的时间
+    queue: VecDeque<Instant>,
+    // RED的平均队长
+    avg_queue: f64,
+    // 瓶颈空闲下来的时间
+    busy_until: Option<Instant>,
+    // 下一个没有用过的发送机会
+    next_opportunity: u64,
+    // 传播时延是固定的,所以同一方向上的包按进入链路的顺序到达
+    in_flight: VecDeque<(Instant, K, PacketBuf)>,
+}
+
+impl<K> Default for Direction<K> {
+    fn default() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            avg_queue: 0.0,
+            busy_until: None,
+            next_opportunity: 0,
+            in_flight: VecDeque::new(),
+        }
+    }
+}
+
+/// 在连接之间传送包的介质,`packet_loop`通过它收发
+pub(crate) trait Medium: Send {
+    /// 把连接`from`发出的包交给介质,返回false表示包被丢弃
+    fn send_from(&mut self, now: Instant, from: usize, packet: PacketBuf) -> bool;
+    /// 下一次需要调用`poll_delivery`的时间
+    fn next_event(&self) -> Option<Instant>;
+    /// 取出一个在`now`之前送达的包和它的接收方
+    fn poll_delivery(&mut self, now: Instant) -> Option<(usize, PacketBuf)>;
+    fn link_stats(&self) -> Vec<LinkStats>;
+}
+
+/// 两端之间的模拟链路,两个方向各有自己的瓶颈。每个包带着一个标记`K`,送达时原样交还
+pub(crate) struct Link<K = usize> {
+    config: LinkConfig,
+    rng: StdRng,
+    // 发送机会的时间起点
+    epoch: Option<Instant>,
+    directions: [Direction<K>; 2],
+    stats: LinkStats,
+}
+
+impl<K> Link<K> {
+    pub fn new(config: LinkConfig) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            config,
+            rng,
+            epoch: None,
+            directions: Default::default(),
+            stats: LinkStats::default(),
+        }
+    }
+
+    /// 队列是否接纳一个新来的包
+    fn admit(&mut self, dir: usize, now: Instant) -> bool {
+        let direction = &mut self.directions[dir];
+        while direction.queue.front().is_some_and(|&done| done <= now) {
+            direction.queue.pop_front();
+        }
+        let len = direction.queue.len();
+        let queue = match &self.config.queue {
+            Some(queue) => *queue,
+            None => return true,
+        };
+        if len >= queue.limit {
+            return false;
+        }
+        match queue.discipline {
+            QueueDiscipline::DropTail => true,
+            QueueDiscipline::Red { min_threshold, max_threshold, max_p, weight } => {
+                direction.avg_queue = (1.0 - weight) * direction.avg_queue + weight * len as f64;
+                let avg = direction.avg_queue;
+                if avg < min_threshold {
+                    true
+                } else if avg >= max_threshold {
+                    false
+                } else {
+                    let p = max_p * (avg - min_threshold) / (max_threshold - min_threshold);
+                    self.rng.gen::<f64>() >= p
+                }
+            }
+        }
+    }
+
+    /// 包在瓶颈上发送完成的时间
+    fn serve(&mut self, dir: usize, now: Instant, len: usize) -> Instant {
+        let epoch = *self.epoch.get_or_insert(now);
+        let direction = &mut self.directions[dir];
+        let done = if let Some(trace) = &self.config.trace {
+            // 空闲时错过的发送机会不能攒下来
+            while epoch + trace.at(direction.next_opportunity) < now {
+                direction.next_opportunity += 1;
+            }
+            let needed = len.div_ceil(DeliveryTrace::MTU).max(1) as u64;
+            direction.next_opportunity += needed;
+            epoch + trace.at(direction.next_opportunity - 1)
+        } else if let Some(bandwidth) = self.config.bandwidth {
+            let start = direction.busy_until.map_or(now, |busy| busy.max(now));
+            start + Duration::from_nanos(len as u64 * 8 * 1_000_000_000 / bandwidth.max(1))
+        } else {
+            return now;
+        };
+        direction.busy_until = Some(done);
+        direction.queue.push_back(done);
+        self.stats.max_queue = self.stats.max_queue.max(direction.queue.len());
+        done
+    }
+
+    /// 把包从方向`dir`(0或1)交给链路,返回false表示包被丢弃
+    pub fn send(&mut self, now: Instant, dir: usize, tag: K, packet: PacketBuf) -> bool {
+        self.stats.packets += 1;
+        self.stats.bytes += packet.len() as u64;
+        if self.config.mtu.is_some_and(|mtu| packet.len() > mtu) {
+            self.stats.oversized += 1;
+            return false;
+        }
+        if self.rng.gen::<f64>() < self.config.loss {
+            self.stats.dropped += 1;
+            return false;
+        }
+        if !self.admit(dir, now) {
+            self.stats.queue_dropped += 1;
+            return false;
+        }
+        let done = self.serve(dir, now, packet.len());
+        self.directions[dir].in_flight.push_back((done + self.config.delay, tag, packet));
+        true
+    }
+
+    fn earliest(&self) -> Option<usize> {
+        (0..2)
+            .filter_map(|dir| self.directions[dir].in_flight.front().map(|(at, _, _)| (*at, dir)))
+            .min()
+            .map(|(_, dir)| dir)
+    }
+
+    #[inline]
+    pub fn next_delivery(&self) -> Option<Instant> {
+        self.earliest().map(|dir| self.directions[dir].in_flight[0].0)
+    }
+
+    /// 取出一个到达时间不晚于`now`的包
+    pub fn poll(&mut self, now: Instant) -> Option<(K, PacketBuf)> {
+        let dir = self.earliest()?;
+        let in_flight = &mut self.directions[dir].in_flight;
+        match in_flight.front() {
+            Some((at, _, _)) if *at <= now => in_flight.pop_front().map(|(_, tag, packet)| (tag, packet)),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn stats(&self) -> &LinkStats {
+        &self.stats
+    }
+}
+
+/// `Interface`的一对连接之间的链路,发往偶数号和奇数号连接的包各走一个方向
+impl Medium for Link {
+    fn send_from(&mut self, now: Instant, from: usize, packet: PacketBuf) -> bool {
+        let to = from ^ 1;
+        self.send(now, to & 1, to, packet)
+    }
+    fn next_event(&self) -> Option<Instant> {
+        self.next_delivery()
+    }
+    fn poll_delivery(&mut self, now: Instant) -> Option<(usize, PacketBuf)> {
+        self.poll(now)
+    }
+    fn link_stats(&self) -> Vec<LinkStats> {
+        vec![self.stats().clone()]
+    }
+}
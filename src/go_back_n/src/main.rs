@@ -1,27 +1,491 @@
-use std::io::{Read, Write};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-use log::info;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use log::{error, info};
+use rand::{RngCore, SeedableRng};
+use rand::rngs::StdRng;
 
+use go_back_n::cli::ConnectionOpt;
+use go_back_n::connection::Config;
 use go_back_n::Interface;
+use go_back_n::experiment::{self, record_transfer, run_transfer, Sweep, SweepRow, TransferReport};
+use go_back_n::file_transfer::{fetch, serve};
+use go_back_n::topology::{Network, Topology};
+use go_back_n::link::{DeliveryTrace, LinkConfig, QueueConfig, QueueDiscipline};
+use go_back_n::record::Session;
+
+/// 在模拟的有损链路上做Go-Back-N传输实验
+#[derive(Parser)]
+#[command(name = "go_back_n")]
+struct Opt {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 传输一个文件或一段生成的数据,并统计传输性能
+    Transfer(TransferOpt),
+    /// 扫描丢包率、窗口、包大小和超时的组合,以CSV输出吞吐量和重传率
+    Sweep(SweepOpt),
+    /// 用可续传的文件传输协议传输一个文件,可以模拟链路中断
+    File(FileOpt),
+    /// 在JSON描述的多跳拓扑上传输生成的数据
+    Multihop(MultihopOpt),
+    /// 用当前的代码重放`transfer --record`录下的会话,报告第一处不一致
+    Replay {
+        session: PathBuf,
+    },
+}
+
+#[derive(Args)]
+struct MultihopOpt {
+    /// 拓扑描述文件
+    topology: PathBuf,
+    /// 发送方所在的节点
+    #[arg(long)]
+    from: String,
+    /// 接收方所在的节点
+    #[arg(long)]
+    to: String,
+    /// 生成数据的字节数
+    #[arg(long, default_value_t = 64 * 1024)]
+    size: usize,
+    #[command(flatten)]
+    connection: ConnectionOpt,
+    /// 以JSON输出统计结果
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Aqm {
+    DropTail,
+    Red,
+}
+
+/// 瓶颈链路的参数
+#[derive(Args)]
+struct BottleneckOpt {
+    /// 瓶颈带宽,比特每秒
+    #[arg(long)]
+    bandwidth: Option<u64>,
+    /// mahimahi格式的发送机会记录,设置后忽略`--bandwidth`
+    #[arg(long)]
+    trace: Option<PathBuf>,
+    /// 瓶颈前的队列最多容纳的包数,不指定时队列无限长
+    #[arg(long)]
+    queue: Option<usize>,
+    /// 队列的丢包策略
+    #[arg(long, value_enum, default_value = "drop-tail")]
+    aqm: Aqm,
+    /// 链路能通过的最大包长,字节,更长的包被丢弃
+    #[arg(long)]
+    mtu: Option<usize>,
+}
+
+impl BottleneckOpt {
+    fn apply(&self, link: &mut LinkConfig) -> io::Result<()> {
+        link.bandwidth = self.bandwidth;
+        link.mtu = self.mtu;
+        if let Some(path) = &self.trace {
+            link.trace = Some(Arc::new(DeliveryTrace::from_file(path)?));
+        }
+        link.queue = self.queue.map(|limit| QueueConfig {
+            limit,
+            discipline: match self.aqm {
+                Aqm::DropTail => QueueDiscipline::DropTail,
+                Aqm::Red => QueueDiscipline::red(limit),
+            },
+        });
+        Ok(())
+    }
+}
+
+/// 链路损伤和连接参数,不指定的沿用库的默认值
+#[derive(Args)]
+struct LinkOpt {
+    /// 丢包率,0到1之间
+    #[arg(long)]
+    loss: Option<f64>,
+    /// 单向时延,毫秒
+    #[arg(long)]
+    delay: Option<u64>,
+    #[command(flatten)]
+    connection: ConnectionOpt,
+    /// 随机数种子,同时决定生成的数据和丢包
+    #[arg(long)]
+    seed: Option<u64>,
+    #[command(flatten)]
+    bottleneck: BottleneckOpt,
+}
+
+impl LinkOpt {
+    fn link(&self) -> io::Result<LinkConfig> {
+        let mut link = LinkConfig::default();
+        if let Some(loss) = self.loss {
+            link.loss = loss;
+        }
+        if let Some(delay) = self.delay {
+            link.delay = Duration::from_millis(delay);
+        }
+        link.seed = self.seed;
+        self.bottleneck.apply(&mut link)?;
+        link.validate()?;
+        Ok(link)
+    }
+    fn config(&self) -> io::Result<Config> {
+        self.connection.clone().into_config()
+    }
+}
+
+#[derive(Args)]
+struct TransferOpt {
+    /// 要传输的文件,不指定时传输`--size`字节的随机数据
+    file: Option<PathBuf>,
+    /// 生成数据的字节数
+    #[arg(long, default_value_t = 64 * 1024)]
+    size: usize,
+    #[command(flatten)]
+    link: LinkOpt,
+    /// 以JSON输出统计结果
+    #[arg(long)]
+    json: bool,
+    /// 把两端连接的输入和输出录到这个文件,加密的连接录下的是明文
+    #[arg(long, value_name = "PATH")]
+    record: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct FileOpt {
+    /// 发送方的文件
+    source: PathBuf,
+    /// 接收方保存的位置
+    dest: PathBuf,
+    #[command(flatten)]
+    link: LinkOpt,
+    /// 在接收方读到这么多字节后模拟一次链路中断
+    #[arg(long)]
+    interrupt_at: Option<u64>,
+    /// 中断后最多重新连接几次
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+}
+
+/// 可以写成区间的取值
+trait Step: FromStr + Copy {
+    /// 闭区间`[start, stop]`中以`step`为间隔的取值
+    fn range(start: Self, stop: Self, step: Self) -> Result<Vec<Self>, String>;
+}
+
+impl Step for f64 {
+    fn range(start: f64, stop: f64, step: f64) -> Result<Vec<f64>, String> {
+        if step.is_nan() || step <= 0.0 {
+            return Err("step must be positive".to_string());
+        }
+        // 按下标计算并留出舍入误差,避免累加误差漏掉区间终点
+        let n = ((stop - start) / step + 1e-9).floor();
+        if n.is_nan() || n < 0.0 {
+            return Ok(Vec::new());
+        }
+        Ok((0..=n as u64).map(|i| ((start + step * i as f64) * 1e9).round() / 1e9).collect())
+    }
+}
+
+macro_rules! impl_step {
+    ($($t:ty),*) => {$(
+        impl Step for $t {
+            fn range(start: $t, stop: $t, step: $t) -> Result<Vec<$t>, String> {
+                if step == 0 {
+                    return Err("step must be positive".to_string());
+                }
+                Ok((start..=stop).step_by(step as usize).collect())
+            }
+        }
+    )*};
+}
+
+impl_step!(u32, u64);
+
+/// 取值列表,写作`a,b,c`或者闭区间`start:stop:step`
+#[derive(Clone)]
+struct Values<T>(Vec<T>);
+
+impl<T> FromStr for Values<T>
+    where T: Step,
+          T::Err: ToString {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |s: &str| s.trim().parse::<T>().map_err(|e| format!("{:?}: {}", s, e.to_string()));
+        let parts: Vec<&str> = s.split(':').collect();
+        let values = match parts[..] {
+            [start, stop, step] => T::range(parse(start)?, parse(stop)?, parse(step)?)?,
+            [_] => s.split(',').map(parse).collect::<Result<_, _>>()?,
+            _ => return Err(format!("{:?} is neither a list nor start:stop:step", s)),
+        };
+        if values.is_empty() {
+            return Err(format!("{:?} is empty", s));
+        }
+        Ok(Values(values))
+    }
+}
+
+#[derive(Args)]
+struct SweepOpt {
+    /// 丢包率
+    #[arg(long, default_value = "0:0.3:0.05")]
+    loss: Values<f64>,
+    /// 发送窗口大小N
+    #[arg(long, default_value = "4,8,16,32")]
+    window: Values<u32>,
+    /// 每个包携带的最大数据量,字节
+    #[arg(long, default_value = "1024")]
+    body_size: Values<u32>,
+    /// 重传超时,毫秒
+    #[arg(long, default_value = "100")]
+    timeout: Values<u64>,
+    /// 接收方的重排缓冲区大小
+    #[arg(long, default_value = "0")]
+    reorder_buffer: Values<u32>,
+    /// 每种组合运行的次数
+    #[arg(long, default_value_t = 5)]
+    runs: u32,
+    /// 第一次运行的种子,之后依次加一
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// 单向时延,毫秒
+    #[arg(long, default_value_t = 0)]
+    delay: u64,
+    #[command(flatten)]
+    bottleneck: BottleneckOpt,
+    /// 每次传输的数据量,字节
+    #[arg(long, default_value_t = 64 * 1024)]
+    size: usize,
+    /// 输出的CSV文件,不指定时写到标准输出
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// 每次运行的时限,毫秒,超过时记为失败并继续。0表示不限
+    #[arg(long, default_value_t = 60_000)]
+    run_timeout: u64,
+}
+
+fn print_report(report: &TransferReport) {
+    println!("transferred {} bytes in {:.3} s, goodput {:.1} KiB/s", report.bytes, report.elapsed_secs, report.goodput / 1024.0);
+    println!("sha256 {} {}", report.received_sha256, if report.verified { "verified" } else { "MISMATCH" });
+    let s = &report.sender;
+    println!("sender: {} packets, {} retransmitted ({:.1}%), efficiency {:.3}, body size {}",
+             s.packets_sent, s.packets_retransmitted, report.retransmission_ratio * 100.0, report.efficiency, s.body_size);
+    if s.probes_sent != 0 {
+        println!("pmtu: {} probes sent, {} lost", s.probes_sent, s.probes_lost);
+    }
+    let r = &report.receiver;
+    println!("receiver: {} packets, {} duplicates, {} out of order, {} reordered, {} acks sent",
+             r.packets_received, r.duplicates_received, r.out_of_order_received, r.reordered_received, r.acks_sent);
+    if s.rejected_packets + r.rejected_packets != 0 {
+        println!("rejected: {} by sender, {} by receiver", s.rejected_packets, r.rejected_packets);
+    }
+    for (i, l) in report.links.iter().enumerate() {
+        println!("link {}: {} packets, {} bytes, {} dropped, {} dropped by queue, {} oversized, max queue {}",
+                 i, l.packets, l.bytes, l.dropped, l.queue_dropped, l.oversized, l.max_queue);
+    }
+}
+
+fn transfer(opt: TransferOpt) {
+    let payload = match &opt.file {
+        Some(path) => fs::read(path).unwrap_or_else(|e| {
+            error!("{}: {}", path.display(), e);
+            process::exit(1);
+        }),
+        None => {
+            let mut payload = vec![0u8; opt.size];
+            StdRng::seed_from_u64(opt.link.seed.unwrap_or(0)).fill_bytes(&mut payload);
+            payload
+        }
+    };
+
+    let result = opt.link.link().and_then(|link| match &opt.record {
+        Some(path) => {
+            let (report, session) = record_transfer(&payload, link, opt.link.config()?)?;
+            session.save(path)?;
+            Ok(report)
+        }
+        None => run_transfer(&payload, link, opt.link.config()?),
+    });
+    let report = result.unwrap_or_else(|e| {
+        error!("transfer failed: {}", e);
+        process::exit(1);
+    });
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        print_report(&report);
+    }
+    if !report.verified {
+        process::exit(2);
+    }
+}
+
+fn sweep(opt: SweepOpt) -> io::Result<()> {
+    let mut link = LinkConfig { delay: Duration::from_millis(opt.delay), ..LinkConfig::default() };
+    opt.bottleneck.apply(&mut link)?;
+    let sweep = Sweep {
+        losses: opt.loss.0,
+        windows: opt.window.0,
+        body_sizes: opt.body_size.0,
+        timeouts: opt.timeout.0.into_iter().map(Duration::from_millis).collect(),
+        reorder_buffers: opt.reorder_buffer.0,
+        runs: opt.runs,
+        seed: opt.seed,
+        link,
+        payload_size: opt.size,
+        run_timeout: Some(Duration::from_millis(opt.run_timeout)).filter(|limit| !limit.is_zero()),
+    };
+    // 在创建输出文件之前检查所有的取值
+    sweep.validate()?;
+    let mut out: Box<dyn Write> = match &opt.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+    writeln!(out, "{}", SweepRow::CSV_HEADER)?;
+    // 每一行都及时写出,中途中断也能保留已经跑完的结果
+    sweep.run(|row| {
+        row.write_csv(&mut out)?;
+        out.flush()
+    })
+}
+
+/// 读到`remaining`字节之后读操作失败,模拟链路中断
+struct Interrupt<S> {
+    inner: S,
+    remaining: Option<u64>,
+}
+
+impl<S: Read> Read for Interrupt<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match self.remaining {
+            Some(0) => return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "simulated link failure")),
+            Some(remaining) => buf.len().min(remaining as usize),
+            None => buf.len(),
+        };
+        let n = self.inner.read(&mut buf[..len])?;
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= n as u64;
+        }
+        Ok(n)
+    }
+}
+
+impl<S: Write> Write for Interrupt<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn send_file(opt: FileOpt) -> io::Result<()> {
+    let name = opt.source.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "source is not a file name"))?
+        .to_string();
+    let root = opt.source.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
+    let mut attempt = 0;
+    loop {
+        let mut interface = Interface::new(opt.link.link()?, opt.link.config()?);
+        let (mut server, client) = interface.pair();
+        let server = {
+            let root = root.clone();
+            thread::spawn(move || serve(&mut server, &root))
+        };
+        let mut client = Interrupt {
+            inner: client,
+            remaining: if attempt == 0 { opt.interrupt_at } else { None },
+        };
+        let result = fetch(&mut client, &name, &opt.dest);
+        // 成功时等确认送达对方再关闭,失败时直接关闭,让发送方的读写出错退出
+        interface.set_linger(if result.is_ok() { Some(Duration::from_secs(1)) } else { None });
+        drop(interface);
+        if let Err(e) = server.join().expect("server thread panicked") {
+            info!("server: {}", e);
+        }
+        match result {
+            Ok(report) => {
+                println!("fetched {} bytes (resumed from {}) in {} attempt(s), sha256 {}",
+                         report.size, report.resumed_from, attempt + 1,
+                         report.sha256.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+                return Ok(());
+            }
+            Err(e) if attempt < opt.retries => {
+                error!("attempt {} failed: {}, resuming", attempt + 1, e);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn multihop(opt: MultihopOpt) -> io::Result<TransferReport> {
+    let topology = Topology::from_json(&fs::read_to_string(&opt.topology)?)?;
+    let node = |name: &str| topology.node(name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unknown node {:?}", name)));
+    let (from, to) = (node(&opt.from)?, node(&opt.to)?);
+    let mut network = Network::new(topology, opt.connection.into_config()?);
+    network.set_linger(Some(Duration::from_secs(1)));
+    let (sender, receiver) = network.pair(from, to);
+    let mut payload = vec![0u8; opt.size];
+    StdRng::seed_from_u64(0).fill_bytes(&mut payload);
+    let mut report = experiment::transfer(&payload, sender, receiver)?;
+    report.links = network.link_stats();
+    Ok(report)
+}
 
 fn main() {
     pretty_env_logger::init();
-    let i = Interface::default();
-    let (stream1, stream2) = i.pair();
-    let mut stream = stream1;
-    thread::spawn(move || {
-        stream.write_all(b"Hello World").unwrap();
-        thread::sleep(Duration::from_secs(1));
-        stream.write_all(b"Hello again").unwrap();
-    });
-    let mut stream = stream2;
-    thread::spawn(move || {
-        let mut buf = [0u8; 64];
-        loop {
-            let nread = stream.read(&mut buf).unwrap();
-            info!("{}", String::from_utf8_lossy(&buf[..nread]));
+    // 传输停滞时用`kill -USR1`查看所有连接的状态
+    #[cfg(unix)]
+    if let Err(e) = go_back_n::dump_on_signal() {
+        error!("failed to install the SIGUSR1 handler: {}", e);
+    }
+    match Opt::parse().command {
+        Command::Transfer(opt) => transfer(opt),
+        Command::Sweep(opt) => if let Err(e) = sweep(opt) {
+            error!("sweep failed: {}", e);
+            process::exit(1);
         }
-    });
-}
\ No newline at end of file
+        Command::File(opt) => if let Err(e) = send_file(opt) {
+            error!("file transfer failed: {}", e);
+            process::exit(1);
+        }
+        Command::Multihop(opt) => {
+            let json = opt.json;
+            match multihop(opt) {
+                Ok(report) if json => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+                Ok(report) => print_report(&report),
+                Err(e) => {
+                    error!("transfer failed: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        Command::Replay { session } => match Session::load(&session).map(|session| session.replay()) {
+            Ok(Ok(events)) => println!("replayed {} events without divergence", events),
+            Ok(Err(divergence)) => {
+                println!("{}", divergence);
+                process::exit(2);
+            }
+            Err(e) => {
+                error!("{}: {}", session.display(), e);
+                process::exit(1);
+            }
+        },
+    }
+}
@@ -0,0 +1,101 @@
+//! 一个go_back_n连接上的多条逻辑流。每条流有自己的流量控制,但共用连接的序号和重传,
+//! 所以一个包丢失会阻塞其后所有流的数据(队头阻塞)
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::sync::Arc;
+
+use super::{Endpoint, GbnStream};
+
+/// 通过`GbnStream::open_stream`或`GbnStream::accept_stream`得到的逻辑流,丢弃时关闭
+pub struct MuxStream {
+    endpoint: Arc<Endpoint>,
+    id: u16,
+}
+
+impl GbnStream {
+    /// 打开一条新的逻辑流
+    pub fn open_stream(&self) -> Result<MuxStream> {
+        let mut c = self.endpoint.lock();
+        c.check()?;
+        if c.is_closed() {
+            return Err(Error::new(ErrorKind::BrokenPipe, "interface shut down"));
+        }
+        let id = c.open_stream().ok_or_else(|| Error::other("stream ids exhausted"))?;
+        c.send_if_could()?;
+        Ok(MuxStream { endpoint: self.endpoint.clone(), id })
+    }
+    /// 等待对方打开的下一条逻辑流
+    pub fn accept_stream(&self) -> Result<MuxStream> {
+        let mut c = self.endpoint.lock();
+        loop {
+            if let Some(id) = c.accept_stream() {
+                return Ok(MuxStream { endpoint: self.endpoint.clone(), id });
+            }
+            if c.is_closed() {
+                c.check()?;
+                return Err(Error::new(ErrorKind::NotConnected, "interface shut down"));
+            }
+            c = self.endpoint.readable.wait(c).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+impl MuxStream {
+    #[inline]
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+}
+
+impl Write for MuxStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut c = self.endpoint.lock();
+        loop {
+            c.check()?;
+            if c.is_closed() {
+                return Err(Error::new(ErrorKind::BrokenPipe, "interface shut down"));
+            }
+            if buf.is_empty() || c.stream_send_space(self.id) > 0 {
+                break;
+            }
+            c = self.endpoint.writable.wait(c).unwrap_or_else(|e| e.into_inner());
+        }
+        let n = c.enqueue_on(self.id, buf);
+        c.send_if_could()?;
+        Ok(n)
+    }
+    fn flush(&mut self) -> Result<()> {
+        let mut c = self.endpoint.lock();
+        c.check()?;
+        c.send_if_could()
+    }
+}
+
+impl Read for MuxStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut c = self.endpoint.lock();
+        loop {
+            if c.stream_has_incoming(self.id) {
+                let n = c.read_from(self.id, buf);
+                // 读出的数据可能让流量控制窗口的通告变得需要发送
+                c.send_if_could()?;
+                return Ok(n);
+            }
+            if c.stream_is_eof(self.id) {
+                return Ok(0);
+            }
+            if c.is_closed() {
+                c.check()?;
+                return Ok(0);
+            }
+            c = self.endpoint.readable.wait(c).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+impl Drop for MuxStream {
+    fn drop(&mut self) {
+        let mut c = self.endpoint.lock();
+        c.close_stream(self.id);
+        let _ = c.send_if_could();
+    }
+}
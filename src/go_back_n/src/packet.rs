@@ -1,58 +1,217 @@
 use std::fmt;
+use std::mem::size_of;
+use std::sync::Arc;
 
 use byteorder::NetworkEndian;
 use intbits;
 use intbits::Bits;
 use zerocopy::{AsBytes, byteorder::{U16, U32}, ByteSlice, FromBytes, LayoutVerified, Unaligned};
 
+/// 一个完整的包(首部+数据),以引用计数共享,重传时无需复制
+pub type PacketBuf = Arc<[u8]>;
+
 #[derive(FromBytes, AsBytes, Unaligned)]
 #[repr(C)]
 pub struct Header {
     pub seq_num: U32<NetworkEndian>,
+    // 捎带的累计确认号,仅当ACK标志位有效时有意义
+    pub ack_num: U32<NetworkEndian>,
     pub flags: U16<NetworkEndian>,
+    pub frame_type: u8,
+    // 逻辑流的编号,0是连接默认的流
+    pub stream_id: U16<NetworkEndian>,
     pub body_len: U32<NetworkEndian>,
 }
 
+/// 包中数据的含义。除了不带数据的DATA包(纯ACK)和路径MTU探测以外,都和数据包一样占用序号、等待确认
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameType {
+    /// 流上的数据
+    Data = 0,
+    /// 打开一条流
+    Open = 1,
+    /// 流量控制:数据是4字节的增量,对方可以在这条流上多发送这么多字节
+    Window = 2,
+    /// 发送方不会再在这条流上发送数据
+    Close = 3,
+    /// 路径MTU探测,数据只是填充。序号字段是探测的编号,丢失后不重传
+    Probe = 4,
+    /// 确认收到了编号相同的探测,数据是4字节的探测长度
+    ProbeAck = 5,
+}
+
+impl FrameType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(FrameType::Data),
+            1 => Some(FrameType::Open),
+            2 => Some(FrameType::Window),
+            3 => Some(FrameType::Close),
+            4 => Some(FrameType::Probe),
+            5 => Some(FrameType::ProbeAck),
+            _ => None,
+        }
+    }
+}
+
 impl Header {
-    pub fn new(seq_num: u32, body_len: u32, is_ack: bool) -> Self {
+    pub const SIZE: usize = size_of::<Header>();
+    /// 已定义的标志位: 第0位ACK,第1位SYN
+    pub const KNOWN_FLAGS: u16 = 0b11;
+    /// 默认流上的数据包
+    pub fn new(seq_num: u32, ack_num: u32, body_len: u32, is_ack: bool) -> Self {
+        Self::new_frame(seq_num, ack_num, FrameType::Data, 0, body_len, is_ack)
+    }
+    pub fn new_frame(seq_num: u32, ack_num: u32, frame_type: FrameType, stream_id: u16, body_len: u32, is_ack: bool) -> Self {
         let mut flags = 0;
         flags.set_bit(0, is_ack);
         Self {
             seq_num: U32::new(seq_num),
+            ack_num: U32::new(ack_num),
             flags: U16::new(flags),
+            frame_type: frame_type as u8,
+            stream_id: U16::new(stream_id),
             body_len: U32::new(body_len),
         }
     }
+    /// 建立连接时发送的SYN包,`seq_num`即本端随机选取的初始序号
+    pub fn new_syn(seq_num: u32) -> Self {
+        let mut flags = 0;
+        flags.set_bit(1, true);
+        Self {
+            seq_num: U32::new(seq_num),
+            ack_num: U32::new(0),
+            flags: U16::new(flags),
+            frame_type: FrameType::Data as u8,
+            stream_id: U16::new(0),
+            body_len: U32::new(0),
+        }
+    }
     pub fn is_ack(&self) -> bool {
         self.flags.get().bit(0)
     }
+    pub fn is_syn(&self) -> bool {
+        self.flags.get().bit(1)
+    }
+    /// 解析过的包中总是已定义的类型
+    pub fn get_frame_type(&self) -> FrameType {
+        FrameType::from_u8(self.frame_type).unwrap_or(FrameType::Data)
+    }
 }
 
 impl fmt::Display for Header {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Packet[{}] {} {}", self.seq_num.get(), ["Normal", "Ack"][self.is_ack() as usize], self.body_len.get())
+        write!(f, "Packet[{}] ", self.seq_num.get())?;
+        match (self.is_syn(), self.body_len.get() != 0, self.is_ack()) {
+            (true, _, _) => write!(f, "Syn")?,
+            _ if self.get_frame_type() != FrameType::Data =>
+                write!(f, "{:?}({})", self.get_frame_type(), self.stream_id.get())?,
+            (false, true, true) => write!(f, "Normal+Ack({})", self.ack_num.get())?,
+            (false, false, true) => write!(f, "Ack({})", self.ack_num.get())?,
+            _ => write!(f, "Normal")?,
+        }
+        write!(f, " {}", self.body_len.get())
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// 长度不足一个首部
+    TooShort(usize),
+    /// 首部中的`body_len`与实际的数据长度不符
+    BodyLengthMismatch { declared: u32, actual: usize },
+    /// 含有未定义的标志位
+    UnknownFlags(u16),
+    /// 未定义的包类型
+    UnknownFrameType(u8),
+    /// 预共享密钥模式下没有通过认证
+    Forged,
+    /// 预共享密钥模式下已经收到过的包
+    Replayed,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::TooShort(len) => write!(f, "packet too short: {} bytes", len),
+            ParseError::BodyLengthMismatch { declared, actual } => write!(f, "body length mismatch: declared {}, actual {}", declared, actual),
+            ParseError::UnknownFlags(flags) => write!(f, "unknown flags: {:#06x}", flags),
+            ParseError::UnknownFrameType(frame_type) => write!(f, "unknown frame type: {}", frame_type),
+            ParseError::Forged => write!(f, "packet failed authentication"),
+            ParseError::Replayed => write!(f, "replayed packet"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 pub struct Packet<B: ByteSlice> {
     pub header: LayoutVerified<B, Header>,
     pub body: B,
 }
 
 impl<B: ByteSlice> Packet<B> {
-    pub fn parse(bytes: B) -> Option<Self> {
-        let (header, body) = LayoutVerified::new_unaligned_from_prefix(bytes)?;
-        Some(Self { header, body })
+    pub fn parse(bytes: B) -> Result<Self, ParseError> {
+        let len = bytes.len();
+        let (header, body): (LayoutVerified<B, Header>, B) = LayoutVerified::new_unaligned_from_prefix(bytes)
+            .ok_or(ParseError::TooShort(len))?;
+        let flags = header.flags.get();
+        if flags & !Header::KNOWN_FLAGS != 0 {
+            return Err(ParseError::UnknownFlags(flags));
+        }
+        if FrameType::from_u8(header.frame_type).is_none() {
+            return Err(ParseError::UnknownFrameType(header.frame_type));
+        }
+        let declared = header.body_len.get();
+        if declared as usize != body.len() {
+            return Err(ParseError::BodyLengthMismatch { declared, actual: body.len() });
+        }
+        Ok(Self { header, body })
     }
     pub fn get_seq_num(&self) -> u32 {
         self.header.seq_num.get()
     }
+    pub fn get_ack_num(&self) -> u32 {
+        self.header.ack_num.get()
+    }
     pub fn is_ack(&self) -> bool {
         self.header.is_ack()
     }
+    pub fn is_syn(&self) -> bool {
+        self.header.is_syn()
+    }
+    pub fn has_body(&self) -> bool {
+        self.get_body_len() != 0
+    }
+    pub fn get_frame_type(&self) -> FrameType {
+        self.header.get_frame_type()
+    }
+    pub fn get_stream_id(&self) -> u16 {
+        self.header.stream_id.get()
+    }
+    /// 占用序号的包:带数据的包和除探测以外的控制包。SYN另外处理
+    pub fn is_sequenced(&self) -> bool {
+        match self.get_frame_type() {
+            FrameType::Data => self.has_body(),
+            FrameType::Probe | FrameType::ProbeAck => false,
+            _ => true,
+        }
+    }
 
     pub fn get_body_len(&self) -> u32 {
         self.header.body_len.get()
     }
 }
 
+
+/// 用于日志输出: 能解析时显示首部,否则显示解析错误
+pub struct Summary<'a>(pub &'a [u8]);
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match Packet::parse(self.0) {
+            Ok(packet) => write!(f, "{}", packet.header),
+            Err(e) => write!(f, "Malformed({})", e),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! 录下连接的全部输入和输出,之后用当前的代码重放并比较
+//!
+//! 连接是确定性的:初始序号、配置和按顺序到来的输入(定时器、收到的包、应用的读写)
+//! 决定了它发出的每一个包和交给应用的每一个字节。录制时输入和输出按发生的顺序记在同一个序列里,
+//! 重放时只把输入交给新的连接,再把它录下的序列与原来的逐项比较。
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Result, Write};
+use std::path::Path;
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::connection::{Config, Connection};
+use super::packet::Summary;
+
+/// 录下的一项。带`at`的输入记录了距离录制开始的纳秒数
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Connect { at: u64 },
+    Tick { at: u64 },
+    Packet {
+        at: u64,
+        #[serde(with = "hex")]
+        bytes: Vec<u8>,
+    },
+    /// 应用要求把缓冲区中的数据发出去
+    Flush { at: u64 },
+    Write {
+        stream: u16,
+        #[serde(with = "hex")]
+        bytes: Vec<u8>,
+    },
+    Read { stream: u16, len: usize },
+    OpenStream,
+    AcceptStream,
+    CloseStream { stream: u16 },
+    ShutdownWrite { stream: u16 },
+    ShutdownRead { stream: u16 },
+    Close,
+    /// 交给信道的包,加密之前
+    Sent {
+        #[serde(with = "hex")]
+        bytes: Vec<u8>,
+    },
+    /// `Write`被接受的字节数
+    Enqueued { len: usize },
+    /// `Read`读出的数据
+    Delivered {
+        #[serde(with = "hex")]
+        bytes: Vec<u8>,
+    },
+    /// `OpenStream`或`AcceptStream`得到的流
+    StreamId { stream: Option<u16> },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |at: &u64| *at as f64 / 1e6;
+        match self {
+            Event::Connect { at } => write!(f, "connect at {:.3} ms", ms(at)),
+            Event::Tick { at } => write!(f, "tick at {:.3} ms", ms(at)),
+            Event::Packet { at, bytes } => write!(f, "received {} at {:.3} ms", Summary(bytes), ms(at)),
+            Event::Flush { at } => write!(f, "flush at {:.3} ms", ms(at)),
+            Event::Write { stream, bytes } => write!(f, "write {} bytes to stream {}", bytes.len(), stream),
+            Event::Read { stream, len } => write!(f, "read up to {} bytes from stream {}", len, stream),
+            Event::OpenStream => write!(f, "open a stream"),
+            Event::AcceptStream => write!(f, "accept a stream"),
+            Event::CloseStream { stream } => write!(f, "close stream {}", stream),
+            Event::ShutdownWrite { stream } => write!(f, "shut down writing to stream {}", stream),
+            Event::ShutdownRead { stream } => write!(f, "shut down reading from stream {}", stream),
+            Event::Close => write!(f, "close"),
+            Event::Sent { bytes } => write!(f, "sent {}", Summary(bytes)),
+            Event::Enqueued { len } => write!(f, "enqueued {} bytes", len),
+            Event::Delivered { bytes } => write!(f, "delivered {} bytes", bytes.len()),
+            Event::StreamId { stream } => write!(f, "got stream {:?}", stream),
+        }
+    }
+}
+
+/// 一个连接从建立开始的记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub isn: u32,
+    pub events: Vec<Event>,
+}
+
+/// 正在录制的连接的状态
+pub(crate) struct Recorder {
+    epoch: Instant,
+    recording: Recording,
+}
+
+impl Recorder {
+    pub fn new(epoch: Instant, isn: u32) -> Self {
+        Self { epoch, recording: Recording { isn, events: Vec::new() } }
+    }
+    #[inline]
+    pub fn at(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.epoch).as_nanos() as u64
+    }
+    #[inline]
+    pub fn push(&mut self, event: Event) {
+        self.recording.events.push(event);
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.recording.events.len()
+    }
+    pub fn finish(self) -> Recording {
+        self.recording
+    }
+}
+
+/// 一次会话中所有连接的记录。预共享密钥不会被保存,加密的连接录下的是明文,重放时也不加密
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub config: Config,
+    pub connections: Vec<Recording>,
+}
+
+/// 重放与记录第一处不一致的地方,`None`表示其中一方已经结束
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub connection: usize,
+    pub index: usize,
+    pub expected: Option<Event>,
+    pub actual: Option<Event>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |event: &Option<Event>| event.as_ref().map_or("nothing".to_string(), Event::to_string);
+        write!(f, "connection {} diverged at event {}: expected {}, got {}",
+               self.connection, self.index, show(&self.expected), show(&self.actual))
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+impl Session {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// 用当前的代码重放每个连接,全部一致时返回比较过的事件数
+    pub fn replay(&self) -> std::result::Result<usize, Divergence> {
+        let mut compared = 0;
+        for (i, recording) in self.connections.iter().enumerate() {
+            let replayed = replay_connection(i, &self.config, recording);
+            let expected = &recording.events;
+            if let Some(index) = (0..expected.len().max(replayed.len())).find(|&k| expected.get(k) != replayed.get(k)) {
+                return Err(Divergence {
+                    connection: i,
+                    index,
+                    expected: expected.get(index).cloned(),
+                    actual: replayed.get(index).cloned(),
+                });
+            }
+            compared += expected.len();
+        }
+        Ok(compared)
+    }
+}
+
+/// 把记录中的输入依次交给新的连接,返回它录下的事件。输出由连接自己记录
+fn replay_connection(id: usize, config: &Config, recording: &Recording) -> Vec<Event> {
+    let (tx, rx) = channel();
+    let mut c = Connection::with_config(id, tx, config.clone(), recording.isn);
+    let epoch = Instant::now();
+    let at = |at: u64| epoch + Duration::from_nanos(at);
+    c.start_recording(epoch);
+    for event in &recording.events {
+        // 错误已经体现在录下的输出中,这里不需要处理
+        match event {
+            Event::Connect { at: t } => {
+                let _ = c.connect_at(at(*t));
+            }
+            Event::Tick { at: t } => {
+                let _ = c.on_tick_at(at(*t));
+            }
+            Event::Packet { at: t, bytes } => {
+                let _ = c.on_packet_at(bytes, at(*t));
+            }
+            Event::Flush { at: t } => {
+                let _ = c.send_if_could_at(at(*t));
+            }
+            Event::Write { stream, bytes } => {
+                let _ = c.enqueue_on(*stream, bytes);
+            }
+            Event::Read { stream, len } => {
+                let _ = c.read_from(*stream, &mut vec![0u8; *len]);
+            }
+            Event::OpenStream => {
+                let _ = c.open_stream();
+            }
+            Event::AcceptStream => {
+                let _ = c.accept_stream();
+            }
+            Event::CloseStream { stream } => c.close_stream(*stream),
+            Event::ShutdownWrite { stream } => c.shutdown_write(*stream),
+            Event::ShutdownRead { stream } => c.shutdown_read(*stream),
+            Event::Close => c.close(),
+            Event::Sent { .. } | Event::Enqueued { .. } | Event::Delivered { .. } | Event::StreamId { .. } => {}
+        }
+        while rx.try_recv().is_ok() {}
+    }
+    c.take_recording().map_or_else(Vec::new, |recording| recording.events)
+}
+
+/// 字节串在JSON中写成十六进制
+mod hex {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let s: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        serializer.serialize_str(&s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s.len() % 2 != 0 || !s.is_ascii() {
+            return Err(D::Error::custom("odd number of hex digits"));
+        }
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
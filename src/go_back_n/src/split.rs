@@ -0,0 +1,57 @@
+//! 把`GbnStream`拆成可以分别交给不同线程的读半边和写半边
+use std::io::{Read, Result, Write};
+use std::net::Shutdown;
+use std::sync::Arc;
+
+use super::{Endpoint, GbnStream};
+
+/// 读半边,丢弃时关闭读方向
+pub struct GbnReadHalf {
+    endpoint: Arc<Endpoint>,
+}
+
+/// 写半边,丢弃时关闭写方向,剩余数据发完后对方读到EOF
+pub struct GbnWriteHalf {
+    endpoint: Arc<Endpoint>,
+}
+
+impl GbnStream {
+    pub fn split(self) -> (GbnReadHalf, GbnWriteHalf) {
+        let endpoint = self.endpoint;
+        (GbnReadHalf { endpoint: endpoint.clone() }, GbnWriteHalf { endpoint })
+    }
+}
+
+impl GbnWriteHalf {
+    /// 立即关闭写方向,与丢弃相同但可以得到错误
+    pub fn shutdown(&self) -> Result<()> {
+        self.endpoint.shutdown(Shutdown::Write)
+    }
+}
+
+impl Read for GbnReadHalf {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.endpoint.read(buf)
+    }
+}
+
+impl Write for GbnWriteHalf {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.endpoint.write(buf)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.endpoint.flush()
+    }
+}
+
+impl Drop for GbnReadHalf {
+    fn drop(&mut self) {
+        let _ = self.endpoint.shutdown(Shutdown::Read);
+    }
+}
+
+impl Drop for GbnWriteHalf {
+    fn drop(&mut self) {
+        let _ = self.endpoint.shutdown(Shutdown::Write);
+    }
+}
@@ -0,0 +1,55 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+use std::time::Instant;
+
+/// 按截止时间排序的定时器堆。
+///
+/// 同一个键只保留最早的那个截止时间;连接的定时器被推迟或取消时不必从堆中删除,
+/// 过期后由调用者重新查询连接真正的截止时间即可。
+pub(crate) struct TimerHeap<K> {
+    heap: BinaryHeap<Reverse<(Instant, K)>>,
+    scheduled: HashMap<K, Instant>,
+}
+
+impl<K: Copy + Ord + Hash> Default for TimerHeap<K> {
+    fn default() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            scheduled: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Copy + Ord + Hash> TimerHeap<K> {
+    pub fn schedule(&mut self, deadline: Instant, key: K) {
+        match self.scheduled.get(&key) {
+            Some(&scheduled) if scheduled <= deadline => {}
+            _ => {
+                self.scheduled.insert(key, deadline);
+                self.heap.push(Reverse((deadline, key)));
+            }
+        }
+    }
+    #[inline]
+    pub fn is_scheduled(&self, key: K) -> bool {
+        self.scheduled.contains_key(&key)
+    }
+    #[inline]
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.heap.peek().map(|Reverse((deadline, _))| *deadline)
+    }
+    /// 取出一个截止时间不晚于`now`的键
+    pub fn pop_expired(&mut self, now: Instant) -> Option<K> {
+        match self.heap.peek() {
+            Some(Reverse((deadline, _))) if *deadline <= now => {
+                let Reverse((deadline, key)) = self.heap.pop().unwrap();
+                if self.scheduled.get(&key) == Some(&deadline) {
+                    self.scheduled.remove(&key);
+                }
+                Some(key)
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,403 @@
+//! 多跳拓扑:若干节点由各自有损伤参数的链路相连,包按静态路由逐跳转发
+use std::collections::{HashMap, VecDeque};
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::trace;
+use serde::Deserialize;
+
+use super::{GbnStream, Interface, InterfaceSnapshot};
+use super::connection::Config;
+use super::link::{Link, LinkConfig, LinkStats, Medium, QueueConfig, QueueDiscipline};
+use super::packet::PacketBuf;
+
+pub type NodeId = usize;
+pub type LinkId = usize;
+
+/// 以固定速率从`from`发往`to`的背景流量,和连接的包一样按路由转发,到达后丢弃
+#[derive(Debug, Clone)]
+pub struct CrossTraffic {
+    pub from: NodeId,
+    pub to: NodeId,
+    /// 比特每秒
+    pub rate: u64,
+    pub packet_size: usize,
+}
+
+impl CrossTraffic {
+    /// 相邻两个包的间隔,速率高到间隔不足1纳秒时返回None
+    fn interval(&self) -> Option<Duration> {
+        let bits = (self.packet_size as u64).checked_mul(8 * 1_000_000_000)?;
+        Some(Duration::from_nanos(bits.checked_div(self.rate)?)).filter(|interval| !interval.is_zero())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Topology {
+    nodes: Vec<String>,
+    links: Vec<(NodeId, NodeId, LinkConfig)>,
+    // (所在节点, 目的节点) -> 下一跳
+    routes: HashMap<(NodeId, NodeId), NodeId>,
+    cross_traffic: Vec<CrossTraffic>,
+}
+
+impl Topology {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add_node(&mut self, name: &str) -> NodeId {
+        self.nodes.push(name.to_string());
+        self.nodes.len() - 1
+    }
+    pub fn node(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter().position(|node| node == name)
+    }
+    pub fn node_name(&self, node: NodeId) -> &str {
+        &self.nodes[node]
+    }
+    /// 连接`a`和`b`的双向链路,两个方向各有一个瓶颈。两个节点之间只能有一条链路
+    pub fn add_link(&mut self, a: NodeId, b: NodeId, config: LinkConfig) -> Result<LinkId> {
+        assert!(a < self.nodes.len() && b < self.nodes.len() && a != b, "invalid link {}-{}", a, b);
+        if self.find_link(a, b).is_some() {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  format!("duplicate link between {:?} and {:?}", self.nodes[a], self.nodes[b])));
+        }
+        self.links.push((a, b, config));
+        Ok(self.links.len() - 1)
+    }
+    fn find_link(&self, a: NodeId, b: NodeId) -> Option<(LinkId, usize)> {
+        self.links.iter().enumerate().find_map(|(id, &(x, y, _))| match (x, y) {
+            _ if (x, y) == (a, b) => Some((id, 0)),
+            _ if (x, y) == (b, a) => Some((id, 1)),
+            _ => None,
+        })
+    }
+    /// 在`at`上,发往`dest`的包交给相邻的`next_hop`。同一条路由只能有一个下一跳
+    pub fn add_route(&mut self, at: NodeId, dest: NodeId, next_hop: NodeId) -> Result<()> {
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidInput, msg);
+        if self.find_link(at, next_hop).is_none() {
+            return Err(invalid(format!("no link between {:?} and {:?}", self.nodes[at], self.nodes[next_hop])));
+        }
+        match self.routes.get(&(at, dest)) {
+            Some(&existing) if existing != next_hop => Err(invalid(format!(
+                "conflicting routes from {:?} to {:?}: via {:?} and via {:?}",
+                self.nodes[at], self.nodes[dest], self.nodes[existing], self.nodes[next_hop]))),
+            _ => {
+                self.routes.insert((at, dest), next_hop);
+                Ok(())
+            }
+        }
+    }
+    /// 沿着`path`安装两个方向上的路由
+    pub fn add_path(&mut self, path: &[NodeId]) -> Result<()> {
+        for (i, hop) in path.windows(2).enumerate() {
+            for &dest in &path[i + 1..] {
+                self.add_route(hop[0], dest, hop[1])?;
+            }
+            for &dest in &path[..=i] {
+                self.add_route(hop[1], dest, hop[0])?;
+            }
+        }
+        Ok(())
+    }
+    pub fn add_cross_traffic(&mut self, traffic: CrossTraffic) {
+        assert!(traffic.rate > 0 && traffic.packet_size > 0, "cross traffic must have a rate and a size");
+        assert!(traffic.interval().is_some(), "cross traffic rate {} too high for {} byte packets", traffic.rate, traffic.packet_size);
+        self.cross_traffic.push(traffic);
+    }
+    /// 从JSON描述构造,格式见`TopologyDescription`
+    pub fn from_json(s: &str) -> Result<Self> {
+        let description: TopologyDescription = serde_json::from_str(s).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        description.build()
+    }
+}
+
+/// 拓扑的JSON描述:
+///
+/// ```json
+/// {
+///   "nodes": ["a", "r", "b"],
+///   "links": [{"between": ["a", "r"], "loss": 0.01, "delay_ms": 5},
+///             {"between": ["r", "b"], "bandwidth": 8000000, "queue": 50, "aqm": "red", "mtu": 1500}],
+///   "routes": [["a", "r", "b"]],
+///   "cross_traffic": [{"from": "r", "to": "b", "rate": 2000000, "packet_size": 1000}]
+/// }
+/// ```
+#[derive(Debug, Deserialize)]
+struct TopologyDescription {
+    nodes: Vec<String>,
+    links: Vec<LinkDescription>,
+    #[serde(default)]
+    routes: Vec<Vec<String>>,
+    #[serde(default)]
+    cross_traffic: Vec<CrossTrafficDescription>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LinkDescription {
+    between: [String; 2],
+    #[serde(default)]
+    loss: f64,
+    #[serde(default)]
+    delay_ms: u64,
+    bandwidth: Option<u64>,
+    queue: Option<usize>,
+    #[serde(default)]
+    aqm: Aqm,
+    seed: Option<u64>,
+    mtu: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Aqm {
+    #[default]
+    DropTail,
+    Red,
+}
+
+#[derive(Debug, Deserialize)]
+struct CrossTrafficDescription {
+    from: String,
+    to: String,
+    rate: u64,
+    packet_size: usize,
+}
+
+impl TopologyDescription {
+    fn build(self) -> Result<Topology> {
+        let mut topology = Topology::new();
+        for name in &self.nodes {
+            topology.add_node(name);
+        }
+        let node = |name: &str| topology.node(name)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("unknown node {:?}", name)));
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+        let mut links = Vec::new();
+        for link in self.links {
+            let (a, b) = (node(&link.between[0])?, node(&link.between[1])?);
+            if a == b {
+                return Err(invalid(format!("link from {:?} to itself", link.between[0])));
+            }
+            let config = LinkConfig {
+                loss: link.loss,
+                delay: Duration::from_millis(link.delay_ms),
+                seed: link.seed,
+                bandwidth: link.bandwidth,
+                trace: None,
+                queue: link.queue.map(|limit| QueueConfig {
+                    limit,
+                    discipline: match link.aqm {
+                        Aqm::DropTail => QueueDiscipline::DropTail,
+                        Aqm::Red => QueueDiscipline::red(limit),
+                    },
+                }),
+                mtu: link.mtu,
+            };
+            config.validate().map_err(|e| invalid(format!("link {:?}-{:?}: {}", link.between[0], link.between[1], e)))?;
+            links.push((a, b, config));
+        }
+        let mut paths = Vec::new();
+        for path in &self.routes {
+            paths.push(path.iter().map(|name| node(name)).collect::<Result<Vec<_>>>()?);
+        }
+        let mut cross_traffic = Vec::new();
+        for traffic in &self.cross_traffic {
+            if traffic.rate == 0 || traffic.packet_size == 0 {
+                return Err(invalid("cross traffic must have a rate and a size".to_string()));
+            }
+            let traffic = CrossTraffic {
+                from: node(&traffic.from)?,
+                to: node(&traffic.to)?,
+                rate: traffic.rate,
+                packet_size: traffic.packet_size,
+            };
+            if traffic.interval().is_none() {
+                return Err(invalid(format!("cross traffic rate {} too high for {} byte packets", traffic.rate, traffic.packet_size)));
+            }
+            cross_traffic.push(traffic);
+        }
+        for (a, b, config) in links {
+            topology.add_link(a, b, config).map_err(|e| invalid(e.to_string()))?;
+        }
+        for path in paths {
+            topology.add_path(&path).map_err(|e| invalid(e.to_string()))?;
+        }
+        for traffic in cross_traffic {
+            topology.add_cross_traffic(traffic);
+        }
+        Ok(topology)
+    }
+}
+
+/// 链路上的包要去的地方
+struct Hop {
+    // 链路另一端的节点
+    at: NodeId,
+    dest: NodeId,
+    // 接收的连接,背景流量为None
+    to: Option<usize>,
+    // 还能经过的链路数,用完时丢弃,避免路由环路中的包永远转发下去
+    ttl: u32,
+}
+
+struct Source {
+    traffic: CrossTraffic,
+    interval: Duration,
+    next: Instant,
+    packet: PacketBuf,
+}
+
+/// 逐跳转发的介质
+struct Fabric {
+    links: Vec<Link<Hop>>,
+    // (节点, 相邻节点) -> (链路, 方向)
+    adjacency: HashMap<(NodeId, NodeId), (LinkId, usize)>,
+    routes: HashMap<(NodeId, NodeId), NodeId>,
+    // 连接所在的节点
+    attachments: Arc<Mutex<HashMap<usize, NodeId>>>,
+    sources: Vec<Source>,
+    // 两端在同一个节点上的连接之间的包
+    local: VecDeque<(Instant, usize, PacketBuf)>,
+}
+
+impl Fabric {
+    /// 包最多经过的链路数
+    const HOP_LIMIT: u32 = 64;
+
+    fn new(topology: Topology, attachments: Arc<Mutex<HashMap<usize, NodeId>>>) -> Self {
+        let mut adjacency = HashMap::new();
+        let mut links = Vec::new();
+        for (id, (a, b, config)) in topology.links.into_iter().enumerate() {
+            adjacency.insert((a, b), (id, 0));
+            adjacency.insert((b, a), (id, 1));
+            links.push(Link::new(config));
+        }
+        let now = Instant::now();
+        let sources = topology.cross_traffic.into_iter().map(|traffic| Source {
+            interval: traffic.interval().expect("cross traffic interval checked when added"),
+            next: now,
+            packet: vec![0u8; traffic.packet_size].into(),
+            traffic,
+        }).collect();
+        Self {
+            links,
+            adjacency,
+            routes: topology.routes,
+            attachments,
+            sources,
+            local: VecDeque::new(),
+        }
+    }
+
+    /// 把在`at`上的包交给下一跳,返回false表示包被丢弃
+    fn forward(&mut self, now: Instant, at: NodeId, hop: Hop, packet: PacketBuf) -> bool {
+        if at == hop.dest {
+            if let Some(to) = hop.to {
+                self.local.push_back((now, to, packet));
+            }
+            return true;
+        }
+        let next = match self.routes.get(&(at, hop.dest)) {
+            Some(&next) => next,
+            None => {
+                trace!("Fabric: No route from {} to {}", at, hop.dest);
+                return false;
+            }
+        };
+        if hop.ttl == 0 {
+            trace!("Fabric: Hop limit exceeded at {} on the way to {}", at, hop.dest);
+            return false;
+        }
+        let (link, dir) = self.adjacency[&(at, next)];
+        self.links[link].send(now, dir, Hop { at: next, ttl: hop.ttl - 1, ..hop }, packet)
+    }
+}
+
+impl Medium for Fabric {
+    fn send_from(&mut self, now: Instant, from: usize, packet: PacketBuf) -> bool {
+        let to = from ^ 1;
+        let (at, dest) = {
+            let attachments = self.attachments.lock().unwrap();
+            (attachments[&from], attachments[&to])
+        };
+        self.forward(now, at, Hop { at, dest, to: Some(to), ttl: Self::HOP_LIMIT }, packet)
+    }
+
+    fn next_event(&self) -> Option<Instant> {
+        let local = self.local.front().map(|(at, _, _)| *at);
+        let links = self.links.iter().filter_map(|link| link.next_delivery());
+        let sources = self.sources.iter().map(|source| source.next);
+        local.into_iter().chain(links).chain(sources).min()
+    }
+
+    fn poll_delivery(&mut self, now: Instant) -> Option<(usize, PacketBuf)> {
+        loop {
+            if let Some((_, to, packet)) = self.local.pop_front() {
+                return Some((to, packet));
+            }
+            for i in 0..self.sources.len() {
+                // 长时间没有被调度时不补发积压的背景流量
+                if self.sources[i].next + Duration::from_secs(1) < now {
+                    self.sources[i].next = now;
+                }
+                while self.sources[i].next <= now {
+                    let source = &mut self.sources[i];
+                    source.next += source.interval;
+                    let (from, dest, packet) = (source.traffic.from, source.traffic.to, source.packet.clone());
+                    self.forward(now, from, Hop { at: from, dest, to: None, ttl: Self::HOP_LIMIT }, packet);
+                }
+            }
+            let ready = self.links.iter().enumerate()
+                .filter_map(|(id, link)| link.next_delivery().filter(|at| *at <= now).map(|at| (at, id)))
+                .min();
+            let (_, id) = ready?;
+            let (hop, packet) = self.links[id].poll(now)?;
+            self.forward(now, hop.at, hop, packet);
+        }
+    }
+
+    fn link_stats(&self) -> Vec<LinkStats> {
+        self.links.iter().map(|link| link.stats().clone()).collect()
+    }
+}
+
+/// 在一个拓扑上运行的一组连接
+pub struct Network {
+    interface: Interface,
+    attachments: Arc<Mutex<HashMap<usize, NodeId>>>,
+}
+
+impl Network {
+    pub fn new(topology: Topology, config: Config) -> Self {
+        let attachments = Arc::new(Mutex::new(HashMap::new()));
+        let fabric = Fabric::new(topology, attachments.clone());
+        Self {
+            interface: Interface::with_medium(Box::new(fabric), config),
+            attachments,
+        }
+    }
+    /// 新建一对分别在节点`a`和`b`上的连接
+    pub fn pair(&self, a: NodeId, b: NodeId) -> (GbnStream, GbnStream) {
+        let (left, right) = self.interface.ih.as_ref().unwrap().new_pair(|id| {
+            let mut attachments = self.attachments.lock().unwrap();
+            attachments.insert(id, a);
+            attachments.insert(id + 1, b);
+        });
+        (GbnStream { endpoint: left }, GbnStream { endpoint: right })
+    }
+    /// 每条链路的统计,顺序与`Topology::add_link`相同
+    pub fn link_stats(&self) -> Vec<LinkStats> {
+        self.interface.ih.as_ref().unwrap().medium.lock().unwrap().link_stats()
+    }
+    pub fn set_linger(&mut self, linger: Option<Duration>) {
+        self.interface.set_linger(linger);
+    }
+    pub fn set_recording(&mut self, recording: bool) {
+        self.interface.set_recording(recording);
+    }
+    pub fn snapshot(&self) -> InterfaceSnapshot {
+        self.interface.snapshot()
+    }
+}
@@ -0,0 +1,68 @@
+//! 把TCP连接通过UDP上的go_back_n连接转发到对端
+use std::io::{self, Result};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::thread;
+
+use log::{error, info};
+
+use super::GbnStream;
+use super::udp::UdpInterface;
+
+/// 在`stream`和`tcp`之间双向转发,直到两个方向都结束。一个方向读到EOF后只关闭另一端的写方向
+pub fn relay(stream: GbnStream, mut tcp: TcpStream) -> Result<()> {
+    let (mut reader, mut writer) = stream.split();
+    let upstream = {
+        let mut tcp = tcp.try_clone()?;
+        thread::spawn(move || -> Result<u64> {
+            let copied = io::copy(&mut tcp, &mut writer)?;
+            // 数据发完后对方读到EOF
+            writer.shutdown()?;
+            Ok(copied)
+        })
+    };
+    let downstream = io::copy(&mut reader, &mut tcp);
+    let _ = match downstream {
+        Ok(_) => tcp.shutdown(Shutdown::Write),
+        // 让另一个方向的`read`返回
+        Err(_) => tcp.shutdown(Shutdown::Both),
+    };
+    let upstream = upstream.join().expect("relay thread panicked");
+    info!("Relayed {:?} bytes up, {:?} bytes down", upstream, downstream);
+    upstream?;
+    downstream?;
+    Ok(())
+}
+
+/// 接受本地的TCP连接,每个都通过一个新的go_back_n连接转发给`peer`
+pub fn listen(udp: &UdpInterface, listener: TcpListener, peer: SocketAddr) -> Result<()> {
+    for tcp in listener.incoming() {
+        let tcp = tcp?;
+        let stream = udp.connect(peer)?;
+        thread::spawn(move || {
+            if let Err(e) = relay(stream, tcp) {
+                error!("Relay failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// 接受对端发起的go_back_n连接,每个都转发给`target`的一个新TCP连接
+pub fn forward(udp: &UdpInterface, target: SocketAddr) -> Result<()> {
+    loop {
+        let stream = udp.accept()?;
+        thread::spawn(move || {
+            let result = match TcpStream::connect(target) {
+                Ok(tcp) => relay(stream, tcp),
+                Err(e) => {
+                    // 关闭连接,监听端的TCP客户端才不会一直等下去
+                    let _ = stream.shutdown(Shutdown::Both);
+                    Err(e)
+                }
+            };
+            if let Err(e) = result {
+                error!("Relay to {} failed: {}", target, e);
+            }
+        });
+    }
+}
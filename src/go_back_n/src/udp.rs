@@ -0,0 +1,404 @@
+//! 在UDP上承载go_back_n连接
+//!
+//! 每个数据报是四字节的连接号加上一个完整的包。连接号的最高位表示数据报来自发起连接的一方,
+//! 这样两端各自分配的连接号不会冲突。一个`UdpInterface`可以同时和多个对端建立多个连接。
+use std::collections::{HashMap, VecDeque};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use byteorder::{ByteOrder, NetworkEndian};
+use log::{error, trace};
+use rand::random;
+
+use super::{Endpoint, GbnStream};
+use super::connection::{Config, Connection, PacketWrapper};
+use super::crypto::{NonceHistory, Seal};
+use super::packet::{Header, Packet};
+use super::timer::TimerHeap;
+
+const INITIATOR: u32 = 1 << 31;
+const CONN_ID_SIZE: usize = 4;
+// 多久清理一次已经被丢弃而且数据都已被确认的连接
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 连接在UDP上的去向:对端地址、连接号以及是不是由这一端发起的
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Route {
+    peer: SocketAddr,
+    conn_id: u32,
+    initiated: bool,
+}
+
+#[derive(Default)]
+struct Table {
+    endpoints: HashMap<usize, (Route, Arc<Endpoint>)>,
+    ids: HashMap<Route, usize>,
+    next_id: usize,
+    // 本端发起的下一个连接号。从随机值开始,重启之后的新连接不会撞上对端还留着的旧连接
+    next_conn_id: u32,
+}
+
+impl Table {
+    fn insert(&mut self, route: Route, endpoint: Arc<Endpoint>) {
+        let id = endpoint.lock().id();
+        self.endpoints.insert(id, (route, endpoint));
+        self.ids.insert(route, id);
+    }
+    fn remove(&mut self, id: usize) {
+        if let Some((route, _)) = self.endpoints.remove(&id) {
+            self.ids.remove(&route);
+        }
+    }
+}
+
+struct Shared {
+    socket: UdpSocket,
+    // 给自己发一个空数据报,唤醒阻塞在`recv_from`上的接收线程
+    wake_addr: SocketAddr,
+    config: Config,
+    table: RwLock<Table>,
+    tx: Mutex<Option<Sender<PacketWrapper>>>,
+    timers: Mutex<TimerHeap<usize>>,
+    accept_queue: Mutex<VecDeque<Arc<Endpoint>>>,
+    acceptable: Condvar,
+    closed: AtomicBool,
+    // 预共享密钥模式下已经接受过的SYN
+    syn_nonces: Mutex<NonceHistory>,
+}
+
+impl Shared {
+    fn wake(&self) {
+        if let Err(e) = self.socket.send_to(&[], self.wake_addr) {
+            error!("Udp: Failed to wake receiver: {}", e);
+        }
+    }
+    fn schedule(&self, deadline: Instant, id: usize) {
+        let mut timers = self.timers.lock().unwrap();
+        let earliest = timers.next_deadline();
+        timers.schedule(deadline, id);
+        drop(timers);
+        if earliest.is_none_or(|earliest| deadline < earliest) {
+            self.wake();
+        }
+    }
+    /// 新建连接并登记在`route(table)`上。可能恐慌的构造在拿锁之前进行,不会让表的锁中毒
+    fn new_endpoint(&self, route: impl FnOnce(&mut Table) -> Route) -> Result<Arc<Endpoint>> {
+        let tx = self.tx.lock().unwrap().clone();
+        let tx = tx.ok_or_else(|| Error::new(ErrorKind::NotConnected, "interface shut down"))?;
+        let mut connection = Connection::with_config(0, tx, self.config.clone(), random());
+        connection.set_encapsulation(CONN_ID_SIZE as u32);
+        let mut table = self.table.write().unwrap();
+        connection.set_id(table.next_id);
+        table.next_id += 1;
+        let route = route(&mut table);
+        let endpoint = Endpoint::new(connection);
+        table.insert(route, endpoint.clone());
+        Ok(endpoint)
+    }
+    /// 丢掉用户已经不再持有,而且没有数据要发送的连接
+    fn sweep(&self) {
+        let mut table = self.table.write().unwrap();
+        let stale: Vec<usize> = table.endpoints.iter()
+            .filter(|(_, (_, endpoint))| Arc::strong_count(endpoint) == 1)
+            .filter(|(_, (_, endpoint))| {
+                let c = endpoint.lock();
+                c.is_idle() || c.is_closed()
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in stale {
+            trace!("Udp: Removed Connection[{}]", id);
+            table.remove(id);
+        }
+    }
+}
+
+/// 把连接发出的包送到对应的对端
+fn send_loop(shared: Arc<Shared>, rx: Receiver<PacketWrapper>) {
+    let mut datagram = Vec::new();
+    for packet in rx {
+        let id = packet.id;
+        let (route, endpoint) = match shared.table.read().unwrap().endpoints.get(&id) {
+            Some((route, endpoint)) => (*route, endpoint.clone()),
+            None => continue,
+        };
+        datagram.clear();
+        datagram.resize(CONN_ID_SIZE, 0);
+        NetworkEndian::write_u32(&mut datagram, route.conn_id | if route.initiated { INITIATOR } else { 0 });
+        datagram.extend_from_slice(&packet.data);
+        // UDP本来就不可靠,发送失败和丢包一样交给重传处理
+        if let Err(e) = shared.socket.send_to(&datagram, route.peer) {
+            trace!("Udp: Failed to send to {}: {}", route.peer, e);
+            if is_too_large(&e) {
+                endpoint.lock().on_datagram_too_large(&packet.data);
+            }
+        }
+        if !shared.timers.lock().unwrap().is_scheduled(id) {
+            let deadline = endpoint.lock().next_deadline();
+            if let Some(deadline) = deadline {
+                shared.schedule(deadline, id);
+            }
+        }
+    }
+}
+
+/// 设置DF位,超过路径MTU的数据报不会被分片:本地已知放不下的在发送时以EMSGSIZE失败,
+/// 途中放不下的被路由器丢弃。否则被分片的探测也能到达,探测会一直涨到UDP允许的上限
+#[cfg(target_os = "linux")]
+fn set_dont_fragment(socket: &UdpSocket) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let (level, name, value) = match socket.local_addr()? {
+        SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_DO),
+        SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_DO),
+    };
+    let len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // 参数都指向活着的局部变量,长度与类型一致
+    let ret = unsafe { libc::setsockopt(socket.as_raw_fd(), level, name, &value as *const libc::c_int as *const libc::c_void, len) };
+    if ret != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// 其他平台上不设置,探测的结果可能偏大
+#[cfg(not(target_os = "linux"))]
+fn set_dont_fragment(_socket: &UdpSocket) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_too_large(e: &Error) -> bool {
+    e.raw_os_error() == Some(libc::EMSGSIZE)
+}
+
+#[cfg(not(unix))]
+fn is_too_large(_e: &Error) -> bool {
+    false
+}
+
+/// 接收数据报并处理到期的定时器
+fn recv_loop(shared: Arc<Shared>) {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut last_sweep = Instant::now();
+    while !shared.closed.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        loop {
+            let expired = shared.timers.lock().unwrap().pop_expired(now);
+            let id = match expired {
+                Some(id) => id,
+                None => break,
+            };
+            let endpoint = shared.table.read().unwrap().endpoints.get(&id).map(|(_, endpoint)| endpoint.clone());
+            if let Some(deadline) = endpoint.and_then(|endpoint| endpoint.on_tick(now)) {
+                shared.timers.lock().unwrap().schedule(deadline, id);
+            }
+        }
+        if now - last_sweep >= SWEEP_INTERVAL {
+            shared.sweep();
+            last_sweep = now;
+        }
+        let deadline = shared.timers.lock().unwrap().next_deadline();
+        let timeout = deadline.map_or(SWEEP_INTERVAL, |deadline| deadline.saturating_duration_since(now).min(SWEEP_INTERVAL));
+        // 超时为零会被当成错误
+        if let Err(e) = shared.socket.set_read_timeout(Some(timeout.max(Duration::from_millis(1)))) {
+            error!("Udp: Failed to set read timeout: {}", e);
+        }
+        let (n, from) = match shared.socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => continue,
+            // 之前发往不可达端口的数据报会在这里报错
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused || e.kind() == ErrorKind::ConnectionReset => continue,
+            Err(e) => {
+                error!("Udp: Receive failed: {}", e);
+                break;
+            }
+        };
+        if n == 0 {
+            continue;
+        }
+        if n < CONN_ID_SIZE + Header::SIZE {
+            trace!("Udp: Dropped {}-byte datagram from {}", n, from);
+            continue;
+        }
+        let tag = NetworkEndian::read_u32(&buf[..CONN_ID_SIZE]);
+        let packet = &buf[CONN_ID_SIZE..n];
+        let route = Route {
+            peer: from,
+            conn_id: tag & !INITIATOR,
+            initiated: tag & INITIATOR == 0,
+        };
+        let found = {
+            let table = shared.table.read().unwrap();
+            table.ids.get(&route).map(|id| (*id, table.endpoints[id].1.clone()))
+        };
+        let (id, endpoint) = match found {
+            Some(found) => found,
+            None => match accept(&shared, route, packet) {
+                Some(accepted) => accepted,
+                None => continue,
+            },
+        };
+        if let Some(deadline) = endpoint.on_packet(packet, Instant::now()) {
+            shared.timers.lock().unwrap().schedule(deadline, id);
+        }
+    }
+    for (_, endpoint) in shared.table.read().unwrap().endpoints.values() {
+        endpoint.fail(ErrorKind::ConnectionReset);
+    }
+}
+
+/// 数据报是不是一个新连接的SYN。预共享密钥模式下SYN要先通过认证,而且不能是重放的
+fn is_new_syn(shared: &Shared, packet: &[u8]) -> bool {
+    match &shared.config.psk {
+        Some(psk) => match Seal::new(psk).open(packet) {
+            Ok(plain) => Packet::parse(plain.as_slice()).is_ok_and(|p| p.is_syn()) && shared.syn_nonces.lock().unwrap().insert(packet),
+            Err(_) => false,
+        },
+        None => Packet::parse(packet).is_ok_and(|p| p.is_syn()),
+    }
+}
+
+/// 对端发起的新连接,只接受SYN
+fn accept(shared: &Shared, route: Route, packet: &[u8]) -> Option<(usize, Arc<Endpoint>)> {
+    if route.initiated || !is_new_syn(shared, packet) {
+        trace!("Udp: Ignored packet for unknown connection {:?}", route);
+        return None;
+    }
+    let endpoint = shared.new_endpoint(|_| route).ok()?;
+    let id = {
+        let mut c = endpoint.lock();
+        c.connect().ok()?;
+        c.id()
+    };
+    trace!("Udp: Accepted Connection[{}] from {}", id, route.peer);
+    shared.accept_queue.lock().unwrap().push_back(endpoint.clone());
+    shared.acceptable.notify_all();
+    Some((id, endpoint))
+}
+
+/// 绑定在一个UDP套接字上的一组go_back_n连接
+pub struct UdpInterface {
+    shared: Arc<Shared>,
+    handles: Vec<JoinHandle<()>>,
+    linger: Option<Duration>,
+}
+
+impl UdpInterface {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Self::bind_with_config(addr, Config::default())
+    }
+
+    /// `config.max_datagram_size`是整个UDP数据报的长度,其中包括连接编号。超出范围的参数返回`InvalidInput`
+    pub fn bind_with_config<A: ToSocketAddrs>(addr: A, mut config: Config) -> Result<Self> {
+        let overhead = CONN_ID_SIZE + config.overhead();
+        if config.max_datagram_size as usize <= overhead {
+            return Err(Error::new(ErrorKind::InvalidInput, format!(
+                "max datagram size must exceed the {} byte header, got {}", overhead, config.max_datagram_size)));
+        }
+        config.max_datagram_size -= CONN_ID_SIZE as u32;
+        config.validate()?;
+        let socket = UdpSocket::bind(addr)?;
+        if config.pmtu_probing {
+            set_dont_fragment(&socket)?;
+        }
+        let mut wake_addr = socket.local_addr()?;
+        if wake_addr.ip().is_unspecified() {
+            wake_addr.set_ip(match wake_addr.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        let (tx, rx) = channel();
+        let shared = Arc::new(Shared {
+            socket,
+            wake_addr,
+            config,
+            table: RwLock::new(Table { next_conn_id: random(), ..Table::default() }),
+            tx: Mutex::new(Some(tx)),
+            timers: Mutex::new(TimerHeap::default()),
+            accept_queue: Mutex::new(VecDeque::new()),
+            acceptable: Condvar::new(),
+            closed: AtomicBool::new(false),
+            syn_nonces: Mutex::new(NonceHistory::default()),
+        });
+        let handles = vec![
+            {
+                let shared = shared.clone();
+                thread::spawn(move || send_loop(shared, rx))
+            },
+            {
+                let shared = shared.clone();
+                thread::spawn(move || recv_loop(shared))
+            },
+        ];
+        Ok(Self { shared, handles, linger: None })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.shared.socket.local_addr()
+    }
+
+    /// 设置`UdpInterface`被丢弃时最多等待多久让已发送的数据被确认,`None`表示立即关闭
+    pub fn set_linger(&mut self, linger: Option<Duration>) {
+        self.linger = linger;
+    }
+
+    /// 向`peer`发起一个新连接
+    pub fn connect(&self, peer: SocketAddr) -> Result<GbnStream> {
+        let endpoint = self.shared.new_endpoint(|table| loop {
+            let conn_id = table.next_conn_id & !INITIATOR;
+            table.next_conn_id = table.next_conn_id.wrapping_add(1);
+            let route = Route { peer, conn_id, initiated: true };
+            if !table.ids.contains_key(&route) {
+                break route;
+            }
+        })?;
+        endpoint.lock().connect()?;
+        Ok(GbnStream { endpoint })
+    }
+
+    /// 等待对端发起的下一个连接
+    pub fn accept(&self) -> Result<GbnStream> {
+        let mut queue = self.shared.accept_queue.lock().unwrap();
+        loop {
+            if let Some(endpoint) = queue.pop_front() {
+                return Ok(GbnStream { endpoint });
+            }
+            if self.shared.closed.load(Ordering::SeqCst) {
+                return Err(Error::new(ErrorKind::NotConnected, "interface shut down"));
+            }
+            queue = self.shared.acceptable.wait(queue).unwrap();
+        }
+    }
+}
+
+impl Drop for UdpInterface {
+    fn drop(&mut self) {
+        let endpoints: Vec<Arc<Endpoint>> = self.shared.table.read().unwrap()
+            .endpoints.values().map(|(_, endpoint)| endpoint.clone()).collect();
+        if let Some(linger) = self.linger {
+            let deadline = Instant::now() + linger;
+            for endpoint in &endpoints {
+                endpoint.linger(deadline);
+            }
+        }
+        drop(self.shared.tx.lock().unwrap().take());
+        for endpoint in &endpoints {
+            endpoint.close();
+        }
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.shared.acceptable.notify_all();
+        self.shared.wake();
+        for handle in self.handles.drain(..) {
+            if handle.join().is_err() {
+                error!("udp loop panicked");
+            }
+        }
+    }
+}
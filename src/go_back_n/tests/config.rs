@@ -0,0 +1,83 @@
+//! 连接和链路参数的检查: 超出范围的参数在建立连接之前以`InvalidInput`报告,
+//! 不小心用它们新建连接时恐慌也不会让`Interface`的锁中毒。
+
+use std::io::{ErrorKind, Read, Write};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use go_back_n::connection::Config;
+use go_back_n::crypto::Psk;
+use go_back_n::link::{LinkConfig, QueueConfig, QueueDiscipline};
+use go_back_n::udp::UdpInterface;
+use go_back_n::Interface;
+
+fn invalid(config: Config) -> bool {
+    config.validate().map_err(|e| e.kind()) == Err(ErrorKind::InvalidInput)
+}
+
+#[test]
+fn out_of_range_configs_are_invalid_input() {
+    assert!(Config::default().validate().is_ok());
+    assert!(invalid(Config { window: 0, ..Config::default() }));
+    assert!(invalid(Config { window: 1, ..Config::default() }));
+    assert!(Config { window: 2, ..Config::default() }.validate().is_ok());
+    assert!(Config { window: 1 << 30, ..Config::default() }.validate().is_ok());
+    assert!(invalid(Config { window: (1 << 30) + 1, ..Config::default() }));
+    assert!(invalid(Config { max_body_size: 0, ..Config::default() }));
+
+    // 数据报至少要比包头多一个字节,有密钥时还要加上认证的开销
+    let header = Config::default().overhead() as u32;
+    assert!(invalid(Config { max_datagram_size: header, ..Config::default() }));
+    assert!(Config { max_datagram_size: header + 1, ..Config::default() }.validate().is_ok());
+    let psk = Config { psk: Some(Psk::new([1; 32])), max_datagram_size: header + 1, ..Config::default() };
+    assert!(invalid(psk.clone()));
+    assert!(Config { max_datagram_size: psk.overhead() as u32 + 1, ..psk }.validate().is_ok());
+}
+
+#[test]
+fn invalid_config_panics_without_poisoning_the_interface() {
+    let interface = Interface::new(LinkConfig::default(), Config { window: 1, ..Config::default() });
+    assert!(catch_unwind(AssertUnwindSafe(|| interface.pair())).is_err());
+    // 锁没有中毒时快照和关闭都照常进行
+    assert!(interface.snapshot().connections.is_empty());
+    drop(interface);
+}
+
+#[test]
+fn out_of_range_links_are_invalid_input() {
+    let link = |loss| LinkConfig { loss, ..LinkConfig::default() };
+    assert!(link(0.0).validate().is_ok());
+    assert!(link(0.999).validate().is_ok());
+    for &loss in &[1.0, 1.5, -0.1, f64::NAN] {
+        assert_eq!(link(loss).validate().unwrap_err().kind(), ErrorKind::InvalidInput, "loss {}", loss);
+    }
+    assert!(LinkConfig { bandwidth: Some(0), ..LinkConfig::default() }.validate().is_err());
+    assert!(LinkConfig { bandwidth: Some(1), ..LinkConfig::default() }.validate().is_ok());
+
+    let queue = |limit, bandwidth| LinkConfig {
+        bandwidth,
+        queue: Some(QueueConfig { limit, discipline: QueueDiscipline::DropTail }),
+        ..LinkConfig::default()
+    };
+    assert!(queue(1, Some(8_000_000)).validate().is_ok());
+    assert_eq!(queue(0, Some(8_000_000)).validate().unwrap_err().kind(), ErrorKind::InvalidInput);
+    // 不限速的链路上队列永远是空的
+    assert_eq!(queue(50, None).validate().unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn udp_datagram_size_covers_the_conn_id_and_the_psk() {
+    let psk = Config { psk: Some(Psk::new([1; 32])), ..Config::default() };
+    // 数据报还要装下四字节的连接号
+    let smallest = psk.overhead() as u32 + 4 + 1;
+    let bind = |config| UdpInterface::bind_with_config("127.0.0.1:0", config).map_err(|e| e.kind());
+    assert_eq!(bind(Config { max_datagram_size: smallest - 1, ..psk.clone() }).err(), Some(ErrorKind::InvalidInput));
+    assert_eq!(bind(Config { window: 1, ..Config::default() }).err(), Some(ErrorKind::InvalidInput));
+
+    let server = bind(Config { max_datagram_size: smallest, ..psk.clone() }).unwrap();
+    let client = bind(Config { max_datagram_size: smallest, ..psk }).unwrap();
+    let mut stream = client.connect(server.local_addr().unwrap()).unwrap();
+    stream.write_all(b"one byte per packet").unwrap();
+    let mut received = [0; 19];
+    server.accept().unwrap().read_exact(&mut received).unwrap();
+    assert_eq!(&received, b"one byte per packet");
+}
@@ -0,0 +1,50 @@
+//! 参数扫描: 取值在运行之前检查,传不完的运行在时限到达时记为失败,扫描继续。
+
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+use go_back_n::experiment::Sweep;
+use go_back_n::link::LinkConfig;
+
+fn sweep(losses: Vec<f64>) -> Sweep {
+    Sweep {
+        losses,
+        windows: vec![4],
+        body_sizes: vec![1024],
+        timeouts: vec![Duration::from_millis(50)],
+        reorder_buffers: vec![0],
+        runs: 2,
+        seed: 0,
+        link: LinkConfig::default(),
+        payload_size: 16 * 1024,
+        run_timeout: Some(Duration::from_millis(300)),
+    }
+}
+
+#[test]
+fn invalid_values_are_rejected_before_running() {
+    let mut rows = 0;
+    let e = sweep(vec![0.0, 1.0]).run(|_| {
+        rows += 1;
+        Ok(())
+    }).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    assert_eq!(rows, 0);
+    assert!(Sweep { windows: vec![1], ..sweep(vec![0.0]) }.validate().is_err());
+    assert!(Sweep { body_sizes: vec![0], ..sweep(vec![0.0]) }.validate().is_err());
+}
+
+#[test]
+fn runs_past_the_deadline_fail_and_the_sweep_moves_on() {
+    let start = Instant::now();
+    let mut rows = Vec::new();
+    sweep(vec![0.99, 0.0]).run(|row| {
+        rows.push(row.clone());
+        Ok(())
+    }).unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5), "the sweep took {:?}", start.elapsed());
+    assert_eq!(rows.len(), 2);
+    assert_eq!((rows[0].loss, rows[0].failures), (0.99, 2));
+    assert_eq!((rows[1].loss, rows[1].failures), (0.0, 0));
+    assert!(rows[1].goodput_mean > 0.0);
+}
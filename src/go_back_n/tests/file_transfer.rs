@@ -0,0 +1,80 @@
+//! 续传时对`<目标>.part`的检查: 只有内容和源文件开头相符时才从它的末尾继续,否则从头传输。
+
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use go_back_n::connection::Config;
+use go_back_n::file_transfer::{fetch, partial_path, serve, FetchReport};
+use go_back_n::link::LinkConfig;
+use go_back_n::Interface;
+
+const SOURCE_SIZE: usize = 100_000;
+
+/// 每个测试自己的目录,里面有源文件`root/source`,目标是`dest`
+struct Fixture {
+    dir: PathBuf,
+    source: Vec<u8>,
+}
+
+impl Fixture {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("gbn_file_transfer_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("root")).unwrap();
+        let source: Vec<u8> = (0..SOURCE_SIZE).map(|i| (i * 7 % 251) as u8).collect();
+        fs::write(dir.join("root/source"), &source).unwrap();
+        Self { dir, source }
+    }
+    fn dest(&self) -> PathBuf {
+        self.dir.join("dest")
+    }
+    fn write_part(&self, content: &[u8]) {
+        fs::write(partial_path(&self.dest()), content).unwrap();
+    }
+    fn fetch(&self) -> FetchReport {
+        let link = LinkConfig { loss: 0.0, ..LinkConfig::default() };
+        let mut interface = Interface::new(link, Config::default());
+        interface.set_linger(Some(Duration::from_secs(1)));
+        let (mut server, mut client) = interface.pair();
+        let root = self.dir.join("root");
+        let server = thread::spawn(move || serve(&mut server, &root));
+        let report = fetch(&mut client, "source", &self.dest()).unwrap();
+        server.join().unwrap().unwrap();
+        assert_eq!(fs::read(self.dest()).unwrap(), self.source);
+        assert!(!partial_path(&self.dest()).exists());
+        report
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn resumes_from_a_matching_part() {
+    let fixture = Fixture::new("matching");
+    fixture.write_part(&fixture.source[..40_000]);
+    assert_eq!(fixture.fetch().resumed_from, 40_000);
+}
+
+#[test]
+fn restarts_when_the_part_is_longer_than_the_source() {
+    let fixture = Fixture::new("longer");
+    let mut part = fixture.source.clone();
+    part.extend_from_slice(b"stale tail");
+    fixture.write_part(&part);
+    assert_eq!(fixture.fetch().resumed_from, 0);
+}
+
+#[test]
+fn restarts_when_the_part_does_not_match_the_source() {
+    let fixture = Fixture::new("mismatch");
+    let mut part = fixture.source[..40_000].to_vec();
+    part[123] ^= 1;
+    fixture.write_part(&part);
+    assert_eq!(fixture.fetch().resumed_from, 0);
+}
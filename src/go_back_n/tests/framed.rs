@@ -0,0 +1,162 @@
+//! 分帧的边界情况: LEB128长度前缀在7位和14位边界上的编码、被截断的前缀和帧、超过上限的长度。
+//! 阻塞和异步两套接口读写同一个内存中的缓冲区,结果应当相同。
+//! 在有丢包的真实连接上,比一个包的数据量大的帧也应当原样收到。
+
+use std::io::{self, Cursor, ErrorKind};
+use std::net::Shutdown;
+use std::thread;
+use std::time::Duration;
+
+use futures::executor::block_on;
+use go_back_n::connection::Config;
+use go_back_n::framed::FramedGbn;
+use go_back_n::link::LinkConfig;
+use go_back_n::{GbnStream, Interface};
+
+/// 长度和它的前缀字节数
+const BOUNDARIES: [(usize, usize); 5] = [(0, 1), (127, 1), (128, 2), (16383, 2), (16384, 3)];
+
+fn frame(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 253) as u8).collect()
+}
+
+/// 用阻塞接口编码一帧,返回线路上的字节
+fn encode(frame: &[u8]) -> Vec<u8> {
+    let mut framed = FramedGbn::new(Cursor::new(Vec::new()));
+    framed.send_frame(frame).unwrap();
+    framed.into_inner().into_inner()
+}
+
+fn recv(bytes: &[u8], max_frame_size: usize) -> io::Result<Vec<u8>> {
+    FramedGbn::with_max_frame_size(Cursor::new(bytes.to_vec()), max_frame_size).recv_frame()
+}
+
+fn recv_async(bytes: &[u8], max_frame_size: usize) -> io::Result<Vec<u8>> {
+    let mut framed = FramedGbn::with_max_frame_size(futures::io::Cursor::new(bytes.to_vec()), max_frame_size);
+    block_on(framed.recv_frame_async())
+}
+
+fn assert_both_fail(bytes: &[u8], max_frame_size: usize, kind: ErrorKind) {
+    assert_eq!(recv(bytes, max_frame_size).unwrap_err().kind(), kind, "blocking recv of {:02x?}", bytes);
+    assert_eq!(recv_async(bytes, max_frame_size).unwrap_err().kind(), kind, "async recv of {:02x?}", bytes);
+}
+
+#[test]
+fn boundary_lengths_round_trip() {
+    for &(len, prefix_len) in BOUNDARIES.iter() {
+        let frame = frame(len);
+        let bytes = encode(&frame);
+        assert_eq!(bytes.len(), prefix_len + len, "prefix of a {} byte frame", len);
+        assert_eq!(&bytes[prefix_len..], &frame[..]);
+
+        let mut framed = FramedGbn::new(futures::io::Cursor::new(Vec::new()));
+        block_on(framed.send_frame_async(&frame)).unwrap();
+        assert_eq!(framed.into_inner().into_inner(), bytes, "async encoding of a {} byte frame", len);
+
+        assert_eq!(recv(&bytes, FramedGbn::<()>::MAX_FRAME_SIZE).unwrap(), frame);
+        assert_eq!(recv_async(&bytes, FramedGbn::<()>::MAX_FRAME_SIZE).unwrap(), frame);
+    }
+}
+
+#[test]
+fn consecutive_frames_are_split_at_their_boundaries() {
+    let mut framed = FramedGbn::new(Cursor::new(Vec::new()));
+    for &(len, _) in BOUNDARIES.iter() {
+        framed.send_frame(&frame(len)).unwrap();
+    }
+    framed.get_mut().set_position(0);
+    for &(len, _) in BOUNDARIES.iter() {
+        assert_eq!(framed.recv_frame().unwrap(), frame(len));
+    }
+    assert_eq!(framed.recv_frame().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn truncated_prefixes_and_frames_are_unexpected_eof() {
+    let max = FramedGbn::<()>::MAX_FRAME_SIZE;
+    // 没有数据、前缀的续位置位之后就结束
+    assert_both_fail(&[], max, ErrorKind::UnexpectedEof);
+    assert_both_fail(&[0x80], max, ErrorKind::UnexpectedEof);
+    assert_both_fail(&[0xff, 0xff], max, ErrorKind::UnexpectedEof);
+    // 前缀完整,帧的内容不够
+    for &(len, prefix_len) in BOUNDARIES.iter().filter(|&&(len, _)| len > 0) {
+        let bytes = encode(&frame(len));
+        assert_both_fail(&bytes[..prefix_len + len - 1], max, ErrorKind::UnexpectedEof);
+        assert_both_fail(&bytes[..prefix_len], max, ErrorKind::UnexpectedEof);
+    }
+}
+
+#[test]
+fn oversized_lengths_are_rejected() {
+    let mut framed = FramedGbn::with_max_frame_size(Cursor::new(Vec::new()), 16383);
+    assert_eq!(framed.send_frame(&frame(16384)).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert!(framed.get_ref().get_ref().is_empty(), "a rejected frame was partly written");
+    framed.send_frame(&frame(16383)).unwrap();
+
+    // 长度超过上限时不等帧的内容到达就报错
+    assert_both_fail(&[0x80, 0x80, 0x01], 16383, ErrorKind::InvalidData);
+    assert_eq!(recv(&encode(&frame(16383)), 16383).unwrap(), frame(16383));
+    // 超出u64的长度
+    let mut overflow = vec![0xff; 9];
+    overflow.push(0x02);
+    assert_both_fail(&overflow, usize::MAX, ErrorKind::InvalidData);
+    assert_both_fail(&[0xff; 11], usize::MAX, ErrorKind::InvalidData);
+    // u64::MAX本身可以解码,但超过了任何可以分配的上限
+    let mut largest = vec![0xff; 9];
+    largest.push(0x01);
+    assert_both_fail(&largest, FramedGbn::<()>::MAX_FRAME_SIZE, ErrorKind::InvalidData);
+}
+
+/// 真实连接上发送的帧,其中有几个需要拆成多个包
+fn stream_frames() -> Vec<Vec<u8>> {
+    let body = Config::default().max_body_size as usize;
+    let mut lens: Vec<usize> = BOUNDARIES.iter().map(|&(len, _)| len).collect();
+    lens.extend_from_slice(&[body, body + 1, 5 * body + 3]);
+    lens.into_iter().map(frame).collect()
+}
+
+fn lossy_pair() -> (Interface, GbnStream, GbnStream) {
+    let link = LinkConfig { loss: 0.05, ..LinkConfig::default() };
+    let interface = Interface::new(link, Config { timeout: Duration::from_millis(50), ..Config::default() });
+    let (client, server) = interface.pair();
+    (interface, client, server)
+}
+
+#[test]
+fn frames_round_trip_over_a_lossy_connection() {
+    let (_interface, client, server) = lossy_pair();
+    let sender = thread::spawn(move || {
+        let mut framed = FramedGbn::new(client);
+        for frame in stream_frames() {
+            framed.send_frame(&frame).unwrap();
+        }
+        framed
+    });
+    let mut framed = FramedGbn::new(server);
+    for frame in stream_frames() {
+        assert_eq!(framed.recv_frame().unwrap(), frame, "{} byte frame", frame.len());
+    }
+    sender.join().unwrap().into_inner().shutdown(Shutdown::Write).unwrap();
+    assert_eq!(framed.recv_frame().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn frames_round_trip_over_an_async_connection() {
+    let (_interface, client, server) = lossy_pair();
+    let sender = thread::spawn(move || {
+        let mut framed = FramedGbn::new(client.into_async());
+        block_on(async {
+            for frame in stream_frames() {
+                framed.send_frame_async(&frame).await.unwrap();
+            }
+        });
+        framed
+    });
+    let mut framed = FramedGbn::new(server.into_async());
+    block_on(async {
+        for frame in stream_frames() {
+            assert_eq!(framed.recv_frame_async().await.unwrap(), frame, "{} byte frame", frame.len());
+        }
+    });
+    sender.join().unwrap();
+}
@@ -0,0 +1,154 @@
+//! 多路复用的流量控制和队头阻塞。
+//!
+//! 一条流用完了对方给的额度之后,写入会阻塞,但同一个连接上的其他流照常收发;
+//! 对方读出数据、通告了新的窗口之后,阻塞的写入继续。
+//! 而所有流共用连接的序号,所以一个流的包丢失时,其他流在它之后发出的数据也要等它重传之后才能交付。
+
+use std::io::{Read, Write};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use go_back_n::connection::{Config, Connection, PacketWrapper};
+use go_back_n::link::LinkConfig;
+use go_back_n::packet::PacketBuf;
+use go_back_n::{GbnStream, Interface};
+
+const WINDOW: usize = Connection::STREAM_WINDOW as usize;
+
+fn pattern(seed: u8, len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8 ^ seed).collect()
+}
+
+fn credit(stream: &GbnStream, id: u16) -> Option<u64> {
+    stream.snapshot().streams.iter().find(|s| s.id == id).and_then(|s| s.send_credit)
+}
+
+#[test]
+fn stream_without_credit_blocks_without_stalling_others() {
+    let link = LinkConfig { loss: 0.0, delay: Duration::from_millis(1), ..LinkConfig::default() };
+    let interface = Interface::new(link, Config::default());
+    let (client, server) = interface.pair();
+    let mut blocked = client.open_stream().unwrap();
+    let mut other = client.open_stream().unwrap();
+    let mut blocked_in = server.accept_stream().unwrap();
+    let mut other_in = server.accept_stream().unwrap();
+
+    // 额度和发送缓冲区各能容纳一个窗口,再多的数据要等对方读出
+    let blocked_id = blocked.id();
+    let payload = pattern(1, 3 * WINDOW);
+    let (done_tx, done) = channel();
+    let writer = {
+        let payload = payload.clone();
+        thread::spawn(move || {
+            blocked.write_all(&payload).unwrap();
+            done_tx.send(()).unwrap();
+            blocked
+        })
+    };
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while credit(&client, blocked_id) != Some(0) {
+        assert!(Instant::now() < deadline, "the stream never ran out of credit");
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    // 另一条流不受影响,可以传输比一个窗口多得多的数据
+    let data = pattern(2, 4 * WINDOW);
+    let sender = {
+        let data = data.clone();
+        thread::spawn(move || {
+            other.write_all(&data).unwrap();
+            other
+        })
+    };
+    let mut received = vec![0u8; data.len()];
+    other_in.read_exact(&mut received).unwrap();
+    assert!(received == data);
+    drop(sender.join().unwrap());
+    assert!(done.try_recv().is_err(), "the write finished without credit");
+    assert_eq!(credit(&client, blocked_id), Some(0));
+
+    // 读出数据后对方通告新的窗口,阻塞的写入继续
+    let mut received = vec![0u8; payload.len()];
+    blocked_in.read_exact(&mut received).unwrap();
+    assert!(received == payload);
+    done.recv_timeout(Duration::from_secs(5)).expect("the write did not resume after a window update");
+    drop(writer.join().unwrap());
+}
+
+/// 两个直接相连的`Connection`,包经过测试手动转交
+struct Pair {
+    now: Instant,
+    conns: [Connection; 2],
+    rx: Receiver<PacketWrapper>,
+}
+
+impl Pair {
+    fn new(config: Config) -> Self {
+        let (tx, rx) = channel();
+        let now = Instant::now();
+        let mut pair = Self {
+            now,
+            conns: [
+                Connection::with_config(0, tx.clone(), config.clone(), 0),
+                Connection::with_config(1, tx, config, 1 << 20),
+            ],
+            rx,
+        };
+        for c in pair.conns.iter_mut() {
+            c.connect_at(now).unwrap();
+        }
+        pair.deliver_all();
+        pair
+    }
+    fn sent(&self) -> Vec<(usize, PacketBuf)> {
+        self.rx.try_iter().map(|packet| (packet.id ^ 1, packet.data)).collect()
+    }
+    fn deliver(&mut self, to: usize, packet: &[u8]) {
+        self.conns[to].on_packet_at(packet, self.now).unwrap();
+    }
+    /// 转交所有的包,直到两端都没有新的包发出
+    fn deliver_all(&mut self) {
+        loop {
+            let sent = self.sent();
+            if sent.is_empty() {
+                return;
+            }
+            for (to, packet) in sent {
+                self.deliver(to, &packet);
+            }
+        }
+    }
+}
+
+#[test]
+fn lost_packet_blocks_later_streams() {
+    for &reorder_buffer in &[0, 8] {
+        let mut pair = Pair::new(Config { reorder_buffer, ..Config::default() });
+        let (a, b) = (pair.conns[0].open_stream().unwrap(), pair.conns[0].open_stream().unwrap());
+        pair.conns[0].send_if_could_at(pair.now).unwrap();
+        pair.deliver_all();
+        let accepted = [pair.conns[1].accept_stream().unwrap(), pair.conns[1].accept_stream().unwrap()];
+
+        pair.conns[0].enqueue_on(a, b"first stream");
+        pair.conns[0].enqueue_on(b, b"second stream");
+        pair.conns[0].send_if_could_at(pair.now).unwrap();
+        let sent = pair.sent();
+        assert_eq!(sent.len(), 2);
+        // 第一个包丢失,第二个包属于另一条流,也不能交付
+        pair.deliver(1, &sent[1].1);
+        for &id in &accepted {
+            assert!(!pair.conns[1].stream_has_incoming(id), "stream {} was delivered past a lost packet", id);
+        }
+
+        // 重传之后两条流的数据都交付
+        pair.now = pair.conns[0].next_deadline().unwrap();
+        pair.conns[0].on_tick_at(pair.now).unwrap();
+        pair.deliver_all();
+        let mut buf = [0u8; 64];
+        let n = pair.conns[1].read_from(accepted[0], &mut buf);
+        assert_eq!(&buf[..n], b"first stream");
+        let n = pair.conns[1].read_from(accepted[1], &mut buf);
+        assert_eq!(&buf[..n], b"second stream");
+    }
+}
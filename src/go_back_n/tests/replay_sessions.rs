@@ -0,0 +1,27 @@
+//! 重放`tests/sessions`下录好的会话,连接的行为变化时这里会报告第一处不一致。
+//!
+//! 新的会话用`go_back_n transfer --record tests/sessions/<name>.json`录制。
+//! 有意改变了连接的行为之后需要重新录制。
+
+use std::fs;
+use std::path::Path;
+
+use go_back_n::record::Session;
+
+#[test]
+fn recorded_sessions_replay_without_divergence() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sessions");
+    let mut replayed = 0;
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let session = Session::load(&path).unwrap();
+        if let Err(divergence) = session.replay() {
+            panic!("{}: {}", path.display(), divergence);
+        }
+        replayed += 1;
+    }
+    assert!(replayed > 0, "no sessions in {}", dir.display());
+}
@@ -0,0 +1,248 @@
+//! 序号回绕的性质测试。
+//!
+//! 两个`Connection`从任意(尤其是靠近`u32::MAX`和`2^31`的)初始序号开始,经过随机的丢包、
+//! 重复和延迟之后检查: 数据按序且恰好交付一次、发送窗口不越界、确认号单调前进。
+//! 接收方可能开启重排缓冲区,这时期望序号一次最多前进缓冲区大小加一。
+//! 失败时proptest会把场景收缩成最小的反例。
+
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+
+use proptest::prelude::*;
+
+use go_back_n::connection::{Config, Connection, PacketWrapper, RecvSequenceSpace, SendSequenceSpace};
+use go_back_n::packet::PacketBuf;
+
+const N: u32 = SendSequenceSpace::N;
+const MAX_EVENTS: usize = 100_000;
+
+/// 信道对每个包的处置,按发送顺序依次取用,用完之后一律正常送达
+#[derive(Debug, Clone, Copy)]
+enum Fate {
+    Deliver,
+    Drop,
+    Duplicate,
+    /// 多等若干步再送达,造成乱序
+    Delay(u8),
+}
+
+#[derive(Debug, Clone)]
+struct Scenario {
+    isn: [u32; 2],
+    payload_len: [usize; 2],
+    reorder_buffer: u32,
+    fates: Vec<Fate>,
+}
+
+fn isn_strategy() -> impl Strategy<Value=u32> {
+    prop_oneof![
+        3 => (u32::MAX - 2 * N)..=u32::MAX,
+        1 => ((1u32 << 31) - 2 * N)..=((1u32 << 31) + 2 * N),
+        1 => any::<u32>(),
+    ]
+}
+
+fn fate_strategy() -> impl Strategy<Value=Fate> {
+    prop_oneof![
+        6 => Just(Fate::Deliver),
+        2 => Just(Fate::Drop),
+        1 => Just(Fate::Duplicate),
+        1 => (1u8..8).prop_map(Fate::Delay),
+    ]
+}
+
+fn scenario_strategy() -> impl Strategy<Value=Scenario> {
+    let payload_len = 0..(48 * Connection::MAX_BODY_SIZE as usize);
+    (
+        [isn_strategy(), isn_strategy()],
+        [payload_len.clone(), payload_len],
+        prop_oneof![Just(0), 1..N],
+        prop::collection::vec(fate_strategy(), 0..256),
+    ).prop_map(|(isn, payload_len, reorder_buffer, fates)| Scenario { isn, payload_len, reorder_buffer, fates })
+}
+
+/// 不以包长为周期的数据,错位交付一定能被发现
+fn payload(side: usize, len: usize) -> Vec<u8> {
+    (0..len as u32).map(|i| (i.wrapping_add(side as u32 * 7919).wrapping_mul(2_654_435_761) >> 24) as u8).collect()
+}
+
+struct Simulation {
+    now: Instant,
+    step: u64,
+    conns: [Connection; 2],
+    rx: Receiver<PacketWrapper>,
+    in_flight: Vec<(u64, usize, PacketBuf)>,
+    fates: std::vec::IntoIter<Fate>,
+    payloads: [Vec<u8>; 2],
+    // 第`i`个连接收到的数据,应当是对端`payloads[1 - i]`的前缀
+    received: [Vec<u8>; 2],
+    bases: [u32; 2],
+    expected: [Option<u32>; 2],
+    reorder_buffer: u32,
+}
+
+impl Simulation {
+    fn new(scenario: &Scenario) -> Self {
+        let (tx, rx) = channel();
+        let config = Config { reorder_buffer: scenario.reorder_buffer, ..Config::default() };
+        let mut conns = [
+            Connection::with_config(0, tx.clone(), config.clone(), scenario.isn[0]),
+            Connection::with_config(1, tx, config, scenario.isn[1]),
+        ];
+        let now = Instant::now();
+        let payloads = [payload(0, scenario.payload_len[0]), payload(1, scenario.payload_len[1])];
+        for (c, data) in conns.iter_mut().zip(payloads.iter()) {
+            c.connect_at(now).unwrap();
+            assert_eq!(c.enqueue(data), data.len());
+            c.send_if_could_at(now).unwrap();
+        }
+        let bases = [conns[0].send_space().base, conns[1].send_space().base];
+        Self {
+            now,
+            step: 0,
+            conns,
+            rx,
+            in_flight: Vec::new(),
+            fates: scenario.fates.clone().into_iter(),
+            payloads,
+            received: [Vec::new(), Vec::new()],
+            bases,
+            expected: [None, None],
+            reorder_buffer: scenario.reorder_buffer,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        (0..2).all(|i| self.received[i].len() == self.payloads[1 - i].len() && self.conns[i].is_idle())
+    }
+
+    fn collect_sent(&mut self) {
+        for packet in self.rx.try_iter() {
+            let to = packet.id ^ 1;
+            let due = self.step + 1;
+            match self.fates.next().unwrap_or(Fate::Deliver) {
+                Fate::Deliver => self.in_flight.push((due, to, packet.data)),
+                Fate::Drop => {}
+                Fate::Duplicate => {
+                    self.in_flight.push((due, to, packet.data.clone()));
+                    self.in_flight.push((due, to, packet.data));
+                }
+                Fate::Delay(steps) => self.in_flight.push((due + steps as u64, to, packet.data)),
+            }
+        }
+    }
+
+    fn check_invariants(&mut self, i: usize) -> Result<(), TestCaseError> {
+        let c = &mut self.conns[i];
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = c.read_incoming(&mut buf);
+            if n == 0 {
+                break;
+            }
+            self.received[i].extend_from_slice(&buf[..n]);
+        }
+        let sent = &self.payloads[1 - i];
+        prop_assert!(self.received[i].len() <= sent.len(), "connection {} received more than was sent", i);
+        prop_assert!(sent.starts_with(&self.received[i]), "connection {} received out-of-order or duplicated data", i);
+
+        let send = c.send_space();
+        prop_assert!(send.unacked_count() <= N as usize, "connection {} exceeded the window: {}", i, send.unacked_count());
+        prop_assert_eq!(send.unacked_count(), c.unacked_len());
+        let advance = send.base.wrapping_sub(self.bases[i]);
+        prop_assert!(advance <= N, "connection {} base moved from {} to {}", i, self.bases[i], send.base);
+        self.bases[i] = send.base;
+
+        if c.is_peer_synced() {
+            let expected = c.recv_space().expected_seq_num;
+            if let Some(old) = self.expected[i] {
+                prop_assert!(expected.wrapping_sub(old) <= self.reorder_buffer + 1, "connection {} expected_seq_num moved from {} to {}", i, old, expected);
+            }
+            self.expected[i] = Some(expected);
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<(), TestCaseError> {
+        for _ in 0..MAX_EVENTS {
+            self.collect_sent();
+            if !self.in_flight.is_empty() {
+                self.step += 1;
+                self.now += Duration::from_millis(1);
+                let step = self.step;
+                let (due, later): (Vec<_>, Vec<_>) = self.in_flight.drain(..).partition(|(at, _, _)| *at <= step);
+                self.in_flight = later;
+                for (_, to, packet) in due {
+                    prop_assert!(self.conns[to].on_packet_at(&packet, self.now).is_ok());
+                    self.check_invariants(to)?;
+                }
+                continue;
+            }
+            if self.is_done() {
+                return Ok(());
+            }
+            let deadline = self.conns.iter().filter_map(Connection::next_deadline).min();
+            prop_assert!(deadline.is_some(), "transfer stalled with no timer armed");
+            self.now = self.now.max(deadline.unwrap());
+            for i in 0..2 {
+                if self.conns[i].next_deadline().is_some_and(|d| d <= self.now) {
+                    self.conns[i].on_tick_at(self.now).unwrap();
+                    self.check_invariants(i)?;
+                }
+            }
+        }
+        Err(TestCaseError::fail("transfer did not finish"))
+    }
+}
+
+proptest! {
+    #[test]
+    fn transfer_survives_impairment(scenario in scenario_strategy()) {
+        let mut sim = Simulation::new(&scenario);
+        sim.run()?;
+        prop_assert_eq!(&sim.received[0], &sim.payloads[1]);
+        prop_assert_eq!(&sim.received[1], &sim.payloads[0]);
+    }
+
+    /// 与用`u64`偏移量表示的模型对比,确认号只在`[base, next_seq_num)`内有效
+    #[test]
+    fn send_space_matches_model(isn in isn_strategy(), ops in prop::collection::vec((0u32..4, -40i64..80), 0..200)) {
+        let mut space = SendSequenceSpace::new(isn);
+        let (mut base, mut next) = (0i64, 0i64);
+        for (sends, ack) in ops {
+            for _ in 0..sends {
+                if space.is_sendable() {
+                    prop_assert_eq!(space.get_next_seq_num_then_inc(), isn.wrapping_add(next as u32));
+                    next += 1;
+                }
+            }
+            prop_assert!(next - base < N as i64);
+            let seq = isn.wrapping_add((base + ack) as u32);
+            let expected = if base + ack < next && ack >= 0 { (ack + 1) as usize } else { 0 };
+            prop_assert_eq!(space.ack(seq), expected);
+            base += expected as i64;
+            prop_assert_eq!(space.base, isn.wrapping_add(base as u32));
+            prop_assert_eq!(space.unacked_count() as i64, next - base);
+        }
+    }
+
+    /// 期望序号之前一个窗口内的包是重复包,其余的包都拒收
+    #[test]
+    fn recv_space_matches_model(isn in isn_strategy(), offsets in prop::collection::vec(-80i64..8, 0..200)) {
+        let mut space = RecvSequenceSpace::new(isn);
+        let mut expected = 0i64;
+        for offset in offsets {
+            let seq = isn.wrapping_add((expected + offset) as u32);
+            let model = match offset {
+                0 => Some(true),
+                o if o < 0 && -o <= N as i64 => Some(false),
+                _ => None,
+            };
+            prop_assert_eq!(space.rcv(seq), model);
+            if model == Some(true) {
+                expected += 1;
+            }
+            prop_assert_eq!(space.expected_seq_num, isn.wrapping_add(expected as u32));
+        }
+    }
+}
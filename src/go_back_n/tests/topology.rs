@@ -0,0 +1,133 @@
+//! 拓扑描述的检查和多跳转发。数据经过多条链路之后原样到达。
+
+use std::io::{ErrorKind, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use go_back_n::connection::Config;
+use go_back_n::experiment;
+use go_back_n::link::LinkConfig;
+use go_back_n::topology::{Network, Topology};
+
+fn with_cross_traffic(rate: u64, packet_size: usize) -> String {
+    format!(r#"{{
+        "nodes": ["a", "r", "b"],
+        "links": [{{"between": ["a", "r"]}}, {{"between": ["r", "b"]}}],
+        "routes": [["a", "r", "b"]],
+        "cross_traffic": [{{"from": "r", "to": "b", "rate": {}, "packet_size": {}}}]
+    }}"#, rate, packet_size)
+}
+
+#[test]
+fn cross_traffic_faster_than_a_packet_per_nanosecond_is_rejected() {
+    assert!(Topology::from_json(&with_cross_traffic(8_000_000_000, 1)).is_ok());
+    let e = Topology::from_json(&with_cross_traffic(8_000_000_001, 1)).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    assert!(Topology::from_json(&with_cross_traffic(u64::MAX, 1500)).is_err());
+}
+
+#[test]
+fn queues_need_a_limit_and_a_bandwidth() {
+    let with_queue = |link: &str| format!(r#"{{
+        "nodes": ["a", "b"],
+        "links": [{{"between": ["a", "b"], {}}}]
+    }}"#, link);
+    assert!(Topology::from_json(&with_queue(r#""bandwidth": 8000000, "queue": 50"#)).is_ok());
+    for link in &[r#""bandwidth": 8000000, "queue": 0"#, r#""queue": 50"#] {
+        let e = Topology::from_json(&with_queue(link)).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData, "{}", link);
+    }
+}
+
+#[test]
+fn conflicting_routes_are_rejected() {
+    let json = r#"{
+        "nodes": ["a", "r", "s", "b"],
+        "links": [{"between": ["a", "r"]}, {"between": ["r", "s"]}, {"between": ["s", "b"]}, {"between": ["r", "b"]}],
+        "routes": [["a", "r", "s", "b"], ["a", "r", "b"]]
+    }"#;
+    let e = Topology::from_json(json).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+    let mut topology = Topology::new();
+    let (a, r, b) = (topology.add_node("a"), topology.add_node("r"), topology.add_node("b"));
+    topology.add_link(a, r, LinkConfig::default()).unwrap();
+    topology.add_link(r, b, LinkConfig::default()).unwrap();
+    topology.add_path(&[a, r, b]).unwrap();
+    // 重复安装相同的路由没有问题
+    topology.add_path(&[a, r, b]).unwrap();
+    assert_eq!(topology.add_route(r, b, a).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(topology.add_route(a, b, b).unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn duplicate_links_are_rejected() {
+    let json = r#"{
+        "nodes": ["a", "r", "b"],
+        "links": [{"between": ["a", "r"]}, {"between": ["r", "b"]}, {"between": ["r", "a"], "delay_ms": 5}],
+        "routes": [["a", "r", "b"]]
+    }"#;
+    let e = Topology::from_json(json).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+    let mut topology = Topology::new();
+    let (a, b) = (topology.add_node("a"), topology.add_node("b"));
+    topology.add_link(a, b, LinkConfig::default()).unwrap();
+    assert_eq!(topology.add_link(a, b, LinkConfig::default()).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(topology.add_link(b, a, LinkConfig::default()).unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn transfer_across_three_hops_arrives_intact() {
+    let link = LinkConfig { loss: 0.02, delay: Duration::from_millis(1), ..LinkConfig::default() };
+    let mut topology = Topology::new();
+    let (a, r, s, b) = (topology.add_node("a"), topology.add_node("r"), topology.add_node("s"), topology.add_node("b"));
+    topology.add_link(a, r, link.clone()).unwrap();
+    topology.add_link(r, s, link.clone()).unwrap();
+    topology.add_link(s, b, link).unwrap();
+    topology.add_path(&[a, r, s, b]).unwrap();
+    let network = Network::new(topology, Config { timeout: Duration::from_millis(50), ..Config::default() });
+    let (sender, receiver) = network.pair(a, b);
+
+    let payload: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+    let report = experiment::transfer(&payload, sender, receiver).unwrap();
+    assert_eq!(report.bytes, payload.len() as u64);
+    assert!(report.verified, "sent {} but received {}", report.sent_sha256, report.received_sha256);
+    // 数据和确认经过了每一条链路
+    for stats in network.link_stats() {
+        assert!(stats.packets > 0, "{:?}", stats);
+    }
+}
+
+#[test]
+fn packets_in_a_routing_loop_are_dropped() {
+    let link = LinkConfig { loss: 0.0, ..LinkConfig::default() };
+    let mut topology = Topology::new();
+    let (a, r, s, b) = (topology.add_node("a"), topology.add_node("r"), topology.add_node("s"), topology.add_node("b"));
+    topology.add_link(a, r, link.clone()).unwrap();
+    topology.add_link(r, s, link.clone()).unwrap();
+    topology.add_link(s, b, link).unwrap();
+    // r和s都认为对方离b更近
+    topology.add_route(a, b, r).unwrap();
+    topology.add_route(r, b, s).unwrap();
+    topology.add_route(s, b, r).unwrap();
+    let config = Config { timeout: Duration::from_secs(60), ..Config::default() };
+    let network = Network::new(topology, config);
+    let (mut sender, _receiver) = network.pair(a, b);
+    sender.write_all(b"lost in a loop").unwrap();
+
+    let looped = || network.link_stats()[1].packets;
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut last = looped();
+    loop {
+        thread::sleep(Duration::from_millis(50));
+        let now = looped();
+        if now > 0 && now == last {
+            break;
+        }
+        assert!(Instant::now() < deadline, "packets kept circling between r and s");
+        last = now;
+    }
+    // 每个包在第一条链路上用掉一跳,之后在环路中走完剩下的63跳
+    assert_eq!(last, network.link_stats()[0].packets * 63);
+}
@@ -0,0 +1,30 @@
+//! TCP隧道: 转发端连不上目标时关闭go_back_n连接,监听端的TCP客户端随后读到EOF。
+
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use go_back_n::tunnel;
+use go_back_n::udp::UdpInterface;
+
+#[test]
+fn closed_target_closes_the_client_connection() {
+    // 绑定后立即释放,这个端口上没有人监听
+    let target = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let forward = UdpInterface::bind("127.0.0.1:0").unwrap();
+    let peer = forward.local_addr().unwrap();
+    thread::spawn(move || tunnel::forward(&forward, target));
+
+    let listen = UdpInterface::bind("127.0.0.1:0").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || tunnel::listen(&listen, listener, peer));
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let mut received = Vec::new();
+    // 连接没有被关闭时这里会超时
+    client.read_to_end(&mut received).expect("the client connection was not closed");
+    assert!(received.is_empty());
+}
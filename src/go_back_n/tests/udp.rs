@@ -0,0 +1,35 @@
+//! UDP上的连接号: 发起连接的一端重启后在同一个地址上再发起连接,对端把它当作新连接接受,
+//! 而不是交给还没有清理掉的旧连接。
+
+use std::io::{Read, Write};
+
+use go_back_n::udp::UdpInterface;
+use go_back_n::GbnStream;
+
+fn echo(client: &mut GbnStream, server: &mut GbnStream, message: &[u8]) {
+    client.write_all(message).unwrap();
+    client.flush().unwrap();
+    let mut received = vec![0; message.len()];
+    server.read_exact(&mut received).unwrap();
+    assert_eq!(received, message);
+}
+
+#[test]
+fn restarted_initiator_gets_a_new_connection() {
+    let server = UdpInterface::bind("127.0.0.1:0").unwrap();
+    let peer = server.local_addr().unwrap();
+
+    let client = UdpInterface::bind("127.0.0.1:0").unwrap();
+    let addr = client.local_addr().unwrap();
+    let mut first = client.connect(peer).unwrap();
+    // 服务器一直持有旧连接,它的路由不会被清理
+    let mut stale = server.accept().unwrap();
+    echo(&mut first, &mut stale, b"first incarnation");
+    drop(first);
+    drop(client);
+
+    let client = UdpInterface::bind(addr).unwrap();
+    let mut second = client.connect(peer).unwrap();
+    let mut fresh = server.accept().unwrap();
+    echo(&mut second, &mut fresh, b"second incarnation");
+}
@@ -0,0 +1,151 @@
+//! 预共享密钥模式下UDP上的握手和攻击。
+//!
+//! 客户端经过一个记录数据报的UDP代理连接服务器,握手完成后代理以自己的地址把记下的数据报
+//! 重放、篡改或者伪造之后发给服务器: 这些数据报都应当被丢弃,既不能交付数据,也不能建立新连接。
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use go_back_n::connection::Config;
+use go_back_n::crypto::Psk;
+use go_back_n::udp::UdpInterface;
+use go_back_n::{snapshot_all, GbnStream};
+
+const INITIATOR: u32 = 1 << 31;
+
+struct Proxy {
+    socket: UdpSocket,
+    addr: SocketAddr,
+    // 客户端发出的数据报,按到达顺序
+    recorded: Arc<Mutex<Vec<Vec<u8>>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Proxy {
+    /// 把收到的数据报在客户端和`server`之间转发
+    fn start(server: SocketAddr) -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let proxy = Self {
+            addr: socket.local_addr().unwrap(),
+            socket: socket.try_clone().unwrap(),
+            recorded: Arc::default(),
+            stop: Arc::default(),
+        };
+        let (recorded, stop) = (proxy.recorded.clone(), proxy.stop.clone());
+        thread::spawn(move || {
+            let mut client = None;
+            let mut buf = [0u8; 65536];
+            while !stop.load(Ordering::SeqCst) {
+                let (n, from) = match socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(_) => continue,
+                };
+                if from == server {
+                    if let Some(client) = client {
+                        let _ = socket.send_to(&buf[..n], client);
+                    }
+                } else {
+                    client = Some(from);
+                    recorded.lock().unwrap().push(buf[..n].to_vec());
+                    let _ = socket.send_to(&buf[..n], server);
+                }
+            }
+        });
+        proxy
+    }
+    fn recorded(&self, i: usize) -> Vec<u8> {
+        self.recorded.lock().unwrap()[i].clone()
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn config() -> Config {
+    Config {
+        timeout: Duration::from_millis(100),
+        psk: Some(Psk::new([7; 32])),
+        ..Config::default()
+    }
+}
+
+fn wait_until<F: FnMut() -> bool>(mut done: F) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while Instant::now() < deadline {
+        if done() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    done()
+}
+
+fn with_conn_id(datagram: &[u8], tag: u32) -> Vec<u8> {
+    let mut datagram = datagram.to_vec();
+    datagram[..4].copy_from_slice(&tag.to_be_bytes());
+    datagram
+}
+
+fn read_exactly(stream: &mut GbnStream, len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn psk_handshake_rejects_forged_tampered_and_replayed_datagrams() {
+    let server = UdpInterface::bind_with_config("127.0.0.1:0", config()).unwrap();
+    let client = UdpInterface::bind_with_config("127.0.0.1:0", config()).unwrap();
+    let proxy = Proxy::start(server.local_addr().unwrap());
+
+    let mut c = client.connect(proxy.addr).unwrap();
+    c.write_all(b"hello").unwrap();
+    let mut s = server.accept().unwrap();
+    assert_eq!(read_exactly(&mut s, 5), b"hello");
+    assert!(wait_until(|| s.stats().packets_received >= 2));
+    let connections = snapshot_all().len();
+
+    let syn = proxy.recorded(0);
+    let data = proxy.recorded(1);
+    let server_addr = server.local_addr().unwrap();
+    let send = |datagram: &[u8]| proxy.socket.send_to(datagram, server_addr).unwrap();
+
+    // 重放握手之后的数据报
+    send(&syn);
+    send(&data);
+    assert!(wait_until(|| s.stats().rejected_packets >= 2), "replayed datagrams were accepted");
+    // 篡改密文
+    let mut tampered = data.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    send(&tampered);
+    assert!(wait_until(|| s.stats().rejected_packets >= 3), "tampered datagram was accepted");
+    // 篡改明文的包头
+    let mut tampered = data.clone();
+    tampered[4] ^= 1;
+    send(&tampered);
+    assert!(wait_until(|| s.stats().rejected_packets >= 4), "datagram with a tampered header was accepted");
+
+    // 用新的连接号重放SYN,或者伪造一个SYN,都不能建立新连接
+    send(&with_conn_id(&syn, INITIATOR | 0x777));
+    let mut forged = with_conn_id(&syn, INITIATOR | 0x778);
+    for byte in forged[4 + 17..].iter_mut() {
+        *byte = rand::random();
+    }
+    send(&forged);
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(snapshot_all().len(), connections, "a forged or replayed SYN opened a connection");
+
+    // 原来的连接不受影响
+    c.write_all(b"still here").unwrap();
+    assert_eq!(read_exactly(&mut s, 10), b"still here");
+    s.write_all(b"bye").unwrap();
+    assert_eq!(read_exactly(&mut c, 3), b"bye");
+}
@@ -0,0 +1,95 @@
+//! 通过模拟的有损链路上的go_back_n连接加载`static/index.html`及其引用的资源,
+//! 输出页面加载时间与丢包率的关系。
+//!
+//! 用法: cargo run --example gbn_page_load -- [单向时延毫秒] [每个丢包率的运行次数]
+use std::net::Shutdown;
+use std::time::{Duration, Instant};
+
+use async_std::io::{Error, ErrorKind, Result};
+use async_std::prelude::*;
+use async_std::task;
+use go_back_n::async_io::AsyncGbnStream;
+use go_back_n::connection::Config;
+use go_back_n::Interface;
+use go_back_n::link::LinkConfig;
+use httparse::{Response, Status};
+
+use static_http_server::StaticServerConnection;
+
+/// 浏览器加载页面时依次请求的资源
+const PAGE: [&str; 4] = ["/index.html", "/index.css", "/index.js", "/favicon.ico"];
+const LOSSES: [f64; 7] = [0.0, 0.05, 0.1, 0.15, 0.2, 0.25, 0.3];
+
+/// 请求`path`,读完响应后返回响应体的长度
+async fn fetch(stream: &mut AsyncGbnStream, path: &str) -> Result<usize> {
+    let request = format!("GET {} HTTP/1.1\r\nHost: gbn\r\nConnection: keep-alive\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte).await?;
+        head.push(byte[0]);
+    }
+    let mut headers = [httparse::EMPTY_HEADER; 16];
+    let mut response = Response::new(&mut headers);
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, format!("{}: {}", path, msg));
+    if !matches!(response.parse(&head), Ok(Status::Complete(_))) {
+        return Err(invalid("malformed response"));
+    }
+    if response.code != Some(200) {
+        return Err(invalid("unexpected status"));
+    }
+    let len = response.headers.iter()
+        .find(|h| h.name.eq_ignore_ascii_case("content-length"))
+        .and_then(|h| std::str::from_utf8(h.value).ok()?.parse().ok())
+        .ok_or_else(|| invalid("missing content length"))?;
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).await?;
+    Ok(len)
+}
+
+/// 新建一对连接,一端由静态服务器提供页面,另一端依次请求页面中的资源
+fn load_page(link: LinkConfig, config: Config) -> Result<(Duration, usize)> {
+    let mut interface = Interface::new(link, config);
+    interface.set_linger(Some(Duration::from_secs(1)));
+    let (client, server) = interface.pair();
+    let static_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/static");
+    let server = task::spawn(StaticServerConnection::from_stream(server.into_async(), "gbn".to_string(), static_dir).serve());
+    let mut client = client.into_async();
+    let start = Instant::now();
+    let bytes = task::block_on(async {
+        let mut bytes = 0;
+        for path in PAGE.iter() {
+            bytes += fetch(&mut client, path).await?;
+        }
+        client.shutdown(Shutdown::Write)?;
+        Ok::<_, Error>(bytes)
+    })?;
+    let elapsed = start.elapsed();
+    task::block_on(server)?;
+    Ok((elapsed, bytes))
+}
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let delay = args.next().map_or(Ok(10), |s| s.parse()).expect("delay must be an integer");
+    let runs: u64 = args.next().map_or(Ok(3), |s| s.parse()).expect("runs must be an integer");
+    let config = Config { timeout: Duration::from_millis(4 * delay + 50), ..Config::default() };
+    println!("loss,runs,bytes,load_ms_mean,load_ms_min,load_ms_max");
+    for &loss in LOSSES.iter() {
+        let mut times = Vec::new();
+        let mut bytes = 0;
+        for seed in 0..runs {
+            let link = LinkConfig { loss, delay: Duration::from_millis(delay), seed: Some(seed), ..LinkConfig::default() };
+            let (elapsed, n) = load_page(link, config.clone())?;
+            times.push(elapsed.as_secs_f64() * 1e3);
+            bytes = n;
+        }
+        let mean = times.iter().sum::<f64>() / times.len() as f64;
+        let min = times.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = times.iter().cloned().fold(0.0, f64::max);
+        println!("{},{},{},{:.1},{:.1},{:.1}", loss, runs, bytes, mean, min, max);
+    }
+    Ok(())
+}
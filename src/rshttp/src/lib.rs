@@ -1,8 +1,8 @@
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::net::SocketAddr;
 
 use async_std::{
     fs::File,
-    io::{BufWriter, copy},
+    io::{BufWriter, copy, Read, Write},
     io::Result,
     net::{TcpListener, TcpStream},
     path::PathBuf,
@@ -18,24 +18,10 @@ pub const ALLOWED_METHODS: [&str; 3] = ["GET", "HEAD", "OPTIONS"];
 pub const BUF_INIT_SIZE: usize = 2048;
 pub const BUF_MAX_SIZE: usize = 8192;
 
-#[cfg(not(target_os = "unix"))]
-async fn send_file(file: &mut File, stream: &mut TcpStream) -> Result<u64> {
+async fn send_file<S: Write + Unpin>(file: &mut File, stream: &mut S) -> Result<u64> {
     copy(file, stream).await
 }
 
-#[cfg(target_os = "unix")]
-async fn send_file(file: &mut File, stream: &mut TcpStream) -> Result<u64> {
-    use async_std::os::unix::io::AsRawFd;
-    use libc::sendfile;
-    unsafe {
-        let out_fd = stream.as_raw_fd();
-        let in_fd = file.as_raw_fd();
-        let offset = 0;
-        sendfile(out_fd, in_fd, &offset, 0);
-        Ok(offset)
-    }
-}
-
 
 #[inline]
 fn spawn_and_log_error<F>(fut: F) -> task::JoinHandle<()>
@@ -64,25 +50,31 @@ pub async fn start_server(addr: SocketAddr) -> Result<()> {
 }
 
 
-pub struct StaticServerConnection {
+/// 在一个连接上提供静态文件。`S`可以是任何异步的字节流,默认是TCP连接
+pub struct StaticServerConnection<S = TcpStream> {
     static_dir: PathBuf,
-    stream: TcpStream,
-    peer_addr: SocketAddr,
+    stream: S,
+    // 只用于日志
+    peer: String,
 }
 
-impl StaticServerConnection {
+impl StaticServerConnection<TcpStream> {
     pub fn new(stream: TcpStream) -> Self {
-        Self {
-            peer_addr: stream.peer_addr().unwrap_or_else(|_| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))),
-            stream,
-            static_dir: PathBuf::from(std::env::current_dir().unwrap().join("static")),
-        }
+        Self::with_dir(stream, "static")
     }
     pub fn with_dir(stream: TcpStream, static_dir: &str) -> Self {
+        let peer = stream.peer_addr().map_or_else(|_| "unknown".to_string(), |addr| addr.to_string());
+        Self::from_stream(stream, peer, static_dir)
+    }
+}
+
+impl<S: Read + Write + Unpin> StaticServerConnection<S> {
+    /// `peer`在日志中标识对端,`static_dir`是相对于当前目录的路径
+    pub fn from_stream(stream: S, peer: String, static_dir: &str) -> Self {
         Self {
-            peer_addr: stream.peer_addr().unwrap_or_else(|_| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))),
+            peer,
             stream,
-            static_dir: PathBuf::from(std::env::current_dir().unwrap().join(static_dir)),
+            static_dir: std::env::current_dir().unwrap().join(static_dir).into(),
         }
     }
     pub async fn serve(mut self) -> Result<()> {
@@ -126,7 +118,7 @@ impl StaticServerConnection {
 
     pub async fn handle_request<'headers, 'buf: 'headers>(&mut self, request: &Request<'headers, 'buf>) -> Result<usize> {
         self.log_request(request);
-        if !request.method.map_or(false, |m| ALLOWED_METHODS.contains(&m)) {
+        if !request.method.is_some_and(|m| ALLOWED_METHODS.contains(&m)) {
             // 方法不允许
             self.send_response(Response::builder()
                 .status(StatusCode::METHOD_NOT_ALLOWED)
@@ -171,7 +163,7 @@ impl StaticServerConnection {
         self.log_response(&response);
         let mut count = 0;
         let (mut parts, body) = response.into_parts();
-        let mut stream = BufWriter::with_capacity(4096, &self.stream);
+        let mut stream = BufWriter::with_capacity(4096, &mut self.stream);
         count += stream.write(b"HTTP/1.1 ").await?;
         count += stream.write(parts.status.as_str().as_bytes()).await?;
         if let Some(reason) = parts.status.canonical_reason() {
@@ -198,7 +190,7 @@ impl StaticServerConnection {
     }
     #[inline]
     pub fn log_request(&self, request: &Request) {
-        info!("{:?} - \"{} {}\"", self.peer_addr, request.method.unwrap_or_default(), request.path.unwrap_or_default());
+        info!("{} - \"{} {}\"", self.peer, request.method.unwrap_or_default(), request.path.unwrap_or_default());
     }
     #[inline]
     pub fn log_response(&self, response: &Response<&[u8]>) {